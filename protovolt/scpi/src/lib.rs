@@ -0,0 +1,364 @@
+//! SCPI-style command parser for the ProtoVolt USB serial port.
+//!
+//! Free of hardware and async dependencies, so it builds and is tested on the
+//! host: `cargo test --target x86_64-unknown-linux-gnu` from this directory,
+//! since the firmware's cargo config defaults to the RP2040 target.
+//! Channel-specific headers take a numeric suffix (`VOLT2 3.3`,
+//! `MEAS:CURR1?`) and default to channel 1 (A) when omitted.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::str::FromStr;
+
+/// Output channel a header addresses, suffix 1 for A and 2 for B
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Channel {
+    A,
+    B,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScpiCommand {
+    /// `*IDN?`
+    Identify,
+    /// `*RST`
+    Reset,
+
+    /// `VOLTage[n] <V>`
+    SetVoltage(Channel, f32),
+    /// `VOLTage[n]?`
+    QueryVoltage(Channel),
+    /// `CURRent[n] <A>`
+    SetCurrent(Channel, f32),
+    /// `CURRent[n]?`
+    QueryCurrent(Channel),
+
+    /// `OUTPut[n] ON|OFF|1|0`
+    SetOutput(Channel, bool),
+    /// `OUTPut[n]?`
+    QueryOutput(Channel),
+    /// `OUTPut[n]:PROTection:CLEar`
+    ClearProtection(Channel),
+
+    /// `MEASure:VOLTage[n]?`, `MEASure:CURRent[n]?`, `MEASure:POWer[n]?`, and
+    /// `MEASure:VOLTage[n]:RAW?`, `MEASure:CURRent[n]:RAW?` before calibration
+    Measure(Channel, Quantity),
+
+    /// `CAPTure:DATA?`, the last burst capture
+    QueryCapture,
+
+    /// `SYSTem:ERRor?`
+    QueryError,
+}
+
+impl ScpiCommand {
+    pub fn is_query(&self) -> bool {
+        matches!(
+            self,
+            ScpiCommand::Identify
+                | ScpiCommand::QueryVoltage(_)
+                | ScpiCommand::QueryCurrent(_)
+                | ScpiCommand::QueryOutput(_)
+                | ScpiCommand::Measure(..)
+                | ScpiCommand::QueryCapture
+                | ScpiCommand::QueryError
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    Voltage,
+    Current,
+    Power,
+    RawVoltage,
+    RawCurrent,
+}
+
+/// Standard SCPI error queue entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScpiError {
+    Syntax,
+    ParameterNotAllowed,
+    MissingParameter,
+    UndefinedHeader,
+    HeaderSuffixOutOfRange,
+    Execution,
+    DataOutOfRange,
+    IllegalParameterValue,
+    DataStale,
+    QueueOverflow,
+}
+
+impl ScpiError {
+    pub fn code(&self) -> i16 {
+        match self {
+            ScpiError::Syntax => -102,
+            ScpiError::ParameterNotAllowed => -108,
+            ScpiError::MissingParameter => -109,
+            ScpiError::UndefinedHeader => -113,
+            ScpiError::HeaderSuffixOutOfRange => -114,
+            ScpiError::Execution => -200,
+            ScpiError::DataOutOfRange => -222,
+            ScpiError::IllegalParameterValue => -224,
+            ScpiError::DataStale => -230,
+            ScpiError::QueueOverflow => -350,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ScpiError::Syntax => "Syntax error",
+            ScpiError::ParameterNotAllowed => "Parameter not allowed",
+            ScpiError::MissingParameter => "Missing parameter",
+            ScpiError::UndefinedHeader => "Undefined header",
+            ScpiError::HeaderSuffixOutOfRange => "Header suffix out of range",
+            ScpiError::Execution => "Execution error",
+            ScpiError::DataOutOfRange => "Data out of range",
+            ScpiError::IllegalParameterValue => "Illegal parameter value",
+            ScpiError::DataStale => "Data stale",
+            ScpiError::QueueOverflow => "Queue overflow",
+        }
+    }
+}
+
+/// Parse a single command, without its line terminator
+pub fn parse(line: &str) -> Result<ScpiCommand, ScpiError> {
+    let line = line.trim();
+
+    let (header, parameter) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((header, parameter)) => (header, Some(parameter.trim())),
+        None => (line, None),
+    };
+    let (header, query) = match header.strip_suffix('?') {
+        Some(header) => (header, true),
+        None => (header, false),
+    };
+
+    if header.is_empty() {
+        return Err(ScpiError::Syntax);
+    }
+
+    if header.starts_with('*') {
+        return parse_common(header, query, parameter);
+    }
+
+    let header = header.strip_prefix(':').unwrap_or(header);
+
+    // Up to three levels deep, with the channel suffix on any one of them
+    let mut nodes = [""; 3];
+    let mut depth = 0;
+    let mut suffix = None;
+
+    for node in header.split(':') {
+        if depth == nodes.len() {
+            return Err(ScpiError::UndefinedHeader);
+        }
+
+        let (mnemonic, node_suffix) = split_suffix(node)?;
+        if let Some(node_suffix) = node_suffix
+            && suffix.replace(node_suffix).is_some()
+        {
+            return Err(ScpiError::HeaderSuffixOutOfRange);
+        }
+
+        nodes[depth] = mnemonic;
+        depth += 1;
+    }
+
+    let channel = match suffix {
+        None | Some(1) => Channel::A,
+        Some(2) => Channel::B,
+        Some(_) => return Err(ScpiError::HeaderSuffixOutOfRange),
+    };
+
+    let nodes = &nodes[..depth];
+    match nodes {
+        [root] if keyword(root, "VOLT", "VOLTAGE") => match query {
+            true => no_parameter(parameter, ScpiCommand::QueryVoltage(channel)),
+            false => Ok(ScpiCommand::SetVoltage(channel, number(parameter)?)),
+        },
+        [root] if keyword(root, "CURR", "CURRENT") => match query {
+            true => no_parameter(parameter, ScpiCommand::QueryCurrent(channel)),
+            false => Ok(ScpiCommand::SetCurrent(channel, number(parameter)?)),
+        },
+        [root] if keyword(root, "OUTP", "OUTPUT") => match query {
+            true => no_parameter(parameter, ScpiCommand::QueryOutput(channel)),
+            false => Ok(ScpiCommand::SetOutput(channel, boolean(parameter)?)),
+        },
+        [root, prot, clear]
+            if keyword(root, "OUTP", "OUTPUT")
+                && keyword(prot, "PROT", "PROTECTION")
+                && keyword(clear, "CLE", "CLEAR")
+                && !query =>
+        {
+            no_parameter(parameter, ScpiCommand::ClearProtection(channel))
+        }
+        [root, quantity] if keyword(root, "MEAS", "MEASURE") && query => {
+            let quantity = match quantity {
+                q if keyword(q, "VOLT", "VOLTAGE") => Quantity::Voltage,
+                q if keyword(q, "CURR", "CURRENT") => Quantity::Current,
+                q if keyword(q, "POW", "POWER") => Quantity::Power,
+                _ => return Err(ScpiError::UndefinedHeader),
+            };
+            no_parameter(parameter, ScpiCommand::Measure(channel, quantity))
+        }
+        [root, quantity, raw]
+            if keyword(root, "MEAS", "MEASURE") && raw.eq_ignore_ascii_case("RAW") && query =>
+        {
+            let quantity = match quantity {
+                q if keyword(q, "VOLT", "VOLTAGE") => Quantity::RawVoltage,
+                q if keyword(q, "CURR", "CURRENT") => Quantity::RawCurrent,
+                _ => return Err(ScpiError::UndefinedHeader),
+            };
+            no_parameter(parameter, ScpiCommand::Measure(channel, quantity))
+        }
+        [root, data] if keyword(root, "CAPT", "CAPTURE") && data.eq_ignore_ascii_case("DATA") => {
+            match (query, suffix) {
+                (true, None) => no_parameter(parameter, ScpiCommand::QueryCapture),
+                _ => Err(ScpiError::UndefinedHeader),
+            }
+        }
+        [root, err] if keyword(root, "SYST", "SYSTEM") && keyword(err, "ERR", "ERROR") => {
+            match (query, suffix) {
+                (true, None) => no_parameter(parameter, ScpiCommand::QueryError),
+                _ => Err(ScpiError::UndefinedHeader),
+            }
+        }
+        _ => Err(ScpiError::UndefinedHeader),
+    }
+}
+
+fn parse_common(
+    header: &str,
+    query: bool,
+    parameter: Option<&str>,
+) -> Result<ScpiCommand, ScpiError> {
+    let command = match (header, query) {
+        (h, true) if h.eq_ignore_ascii_case("*IDN") => ScpiCommand::Identify,
+        (h, false) if h.eq_ignore_ascii_case("*RST") => ScpiCommand::Reset,
+        _ => return Err(ScpiError::UndefinedHeader),
+    };
+
+    no_parameter(parameter, command)
+}
+
+/// Matches either the short or the long form of a mnemonic, case-insensitive
+fn keyword(node: &str, short: &str, long: &str) -> bool {
+    node.eq_ignore_ascii_case(short) || node.eq_ignore_ascii_case(long)
+}
+
+/// Split a trailing numeric suffix off a mnemonic, `VOLT2` into `("VOLT", Some(2))`
+fn split_suffix(node: &str) -> Result<(&str, Option<u8>), ScpiError> {
+    let digits = node.bytes().rev().take_while(u8::is_ascii_digit).count();
+    let (mnemonic, suffix) = node.split_at(node.len() - digits);
+
+    if mnemonic.is_empty() || !mnemonic.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return Err(ScpiError::Syntax);
+    }
+
+    match suffix {
+        "" => Ok((mnemonic, None)),
+        suffix => u8::from_str(suffix)
+            .map(|n| (mnemonic, Some(n)))
+            .map_err(|_| ScpiError::HeaderSuffixOutOfRange),
+    }
+}
+
+fn no_parameter(parameter: Option<&str>, command: ScpiCommand) -> Result<ScpiCommand, ScpiError> {
+    match parameter {
+        None => Ok(command),
+        Some(_) => Err(ScpiError::ParameterNotAllowed),
+    }
+}
+
+fn number(parameter: Option<&str>) -> Result<f32, ScpiError> {
+    let parameter = parameter.ok_or(ScpiError::MissingParameter)?;
+    let value = f32::from_str(parameter).map_err(|_| ScpiError::IllegalParameterValue)?;
+
+    match value.is_finite() {
+        true => Ok(value),
+        false => Err(ScpiError::IllegalParameterValue),
+    }
+}
+
+fn boolean(parameter: Option<&str>) -> Result<bool, ScpiError> {
+    match parameter.ok_or(ScpiError::MissingParameter)? {
+        p if p == "1" || p.eq_ignore_ascii_case("ON") => Ok(true),
+        p if p == "0" || p.eq_ignore_ascii_case("OFF") => Ok(false),
+        _ => Err(ScpiError::IllegalParameterValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify() {
+        assert_eq!(parse("*IDN?"), Ok(ScpiCommand::Identify));
+        assert_eq!(parse("*idn?\r"), Ok(ScpiCommand::Identify));
+    }
+
+    #[test]
+    fn set_voltage_on_channel_2() {
+        assert_eq!(parse("VOLT2 3.3"), Ok(ScpiCommand::SetVoltage(Channel::B, 3.3)));
+        assert_eq!(parse("voltage 5"), Ok(ScpiCommand::SetVoltage(Channel::A, 5.0)));
+    }
+
+    #[test]
+    fn measure_current() {
+        assert_eq!(
+            parse("MEAS:CURR1?"),
+            Ok(ScpiCommand::Measure(Channel::A, Quantity::Current))
+        );
+        assert_eq!(
+            parse(":MEASure:CURRent2?"),
+            Ok(ScpiCommand::Measure(Channel::B, Quantity::Current))
+        );
+    }
+
+    #[test]
+    fn measure_raw_voltage() {
+        assert_eq!(
+            parse("MEAS:VOLT:RAW?"),
+            Ok(ScpiCommand::Measure(Channel::A, Quantity::RawVoltage))
+        );
+        assert_eq!(parse("MEAS:POW:RAW?"), Err(ScpiError::UndefinedHeader));
+    }
+
+    #[test]
+    fn suffix_out_of_range() {
+        assert_eq!(parse("VOLT3 1.0"), Err(ScpiError::HeaderSuffixOutOfRange));
+        assert_eq!(parse("MEAS2:VOLT1?"), Err(ScpiError::HeaderSuffixOutOfRange));
+    }
+
+    #[test]
+    fn missing_parameter() {
+        assert_eq!(parse("VOLT"), Err(ScpiError::MissingParameter));
+        assert_eq!(parse("OUTP2"), Err(ScpiError::MissingParameter));
+    }
+
+    #[test]
+    fn parameter_not_allowed() {
+        assert_eq!(parse("VOLT? 3.3"), Err(ScpiError::ParameterNotAllowed));
+        assert_eq!(parse("*RST 1"), Err(ScpiError::ParameterNotAllowed));
+    }
+
+    #[test]
+    fn illegal_parameter_value() {
+        assert_eq!(parse("CURR abc"), Err(ScpiError::IllegalParameterValue));
+        assert_eq!(parse("OUTP maybe"), Err(ScpiError::IllegalParameterValue));
+    }
+
+    #[test]
+    fn clear_protection() {
+        assert_eq!(parse("OUTP:PROT:CLE"), Ok(ScpiCommand::ClearProtection(Channel::A)));
+        assert_eq!(
+            parse("OUTPut2:PROTection:CLEar"),
+            Ok(ScpiCommand::ClearProtection(Channel::B))
+        );
+        assert_eq!(parse("OUTP:PROT:CLE?"), Err(ScpiError::UndefinedHeader));
+    }
+}
@@ -1,12 +1,40 @@
+use defmt::*;
 use embassy_time::Duration;
 use micromath::F32Ext;
 
+use crate::hal::calibration::Calibration;
 use crate::hal::event::{
-    AppEvent, AppTask, AppTaskBuilder, Change, Channel, ChannelFocus, ConfirmState, DisplayTask,
-    FunctionButton, HardwareEvent, HardwareTask, InterfaceEvent, Limits, PowerType, Readout,
-    SetState,
+    AppEvent, AppTask, AppTaskBuilder, Change, Channel, ChannelFocus, Chip, ChipFault, Chord,
+    ConfirmState, ConverterFault, ConverterRegion, DisplayTask, FaultReason, FunctionButton,
+    HardwareEvent, HardwareTask, InterfaceEvent, Limits, PowerType, ProtectionTrip, Readout,
+    RecoveryAction, RegulationMode, SelfTest, SetState,
 };
 
+pub mod budget;
+mod capture;
+mod compensation;
+pub mod config;
+mod energy;
+mod protection;
+mod regulation;
+mod remote;
+pub mod scpi;
+pub mod settings;
+mod statistics;
+mod wizard;
+
+use budget::Budget;
+use energy::EnergyCounter;
+use protection::Protection;
+use regulation::regulation_mode;
+use remote::ErrorQueue;
+use settings::{ProtectionBehaviour, Settings, SettingsPage};
+use statistics::{ChannelStatistics, ReadoutView};
+use wizard::Wizard;
+
+/// Bottom of the converter range, where ramped outputs start from
+const RAMP_START_VOLTAGE: f32 = 0.2; // V
+
 #[derive(Default)]
 pub struct App {
     power_type: PowerType,
@@ -15,6 +43,20 @@ pub struct App {
     interface_state: InterfaceState,
     hardware_state: HardwareState,
 
+    power_delivery_test: Option<Result<(), ChipFault>>,
+    sense_test: Option<SelfTest>,
+    converter_test: Option<SelfTest>,
+
+    settings: Settings,
+    /// Calibration per channel as last loaded or saved
+    calibration: [Calibration; 2],
+    /// Samples in the last completed burst capture, `None` while one is armed
+    capture_length: Option<usize>,
+    remote_errors: ErrorQueue,
+    budget: Budget,
+    /// Persisted state was restored, which happens once per power on
+    config_loaded: bool,
+
     ch_a: ChannelState,
     ch_b: ChannelState,
 }
@@ -42,7 +84,7 @@ impl DecimalPrecision {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct WithPrecision {
     value: f32,
     precision: DecimalPrecision,
@@ -103,6 +145,7 @@ impl WithPrecision {
     // }
 }
 
+#[derive(Clone)]
 pub struct VoltageCurrentWithSetter {
     pub voltage: WithPrecision,
     pub current: WithPrecision,
@@ -151,14 +194,42 @@ struct ChannelState {
     pub limits: VoltageCurrentWithSetter,
 
     pub readout: Option<Readout>,
+    /// Readouts taken while the output is on
+    pub statistics: ChannelStatistics,
+    /// Charge and energy delivered, integrated from the readouts
+    pub energy: EnergyCounter,
+    pub regulation: Option<RegulationMode>,
+    pub region: Option<ConverterRegion>,
+
+    /// Software cable drop correction on top of the target voltage, V
+    pub cable_trim: f32,
+    /// Closed-loop correction from the bus voltage readout, V
+    pub loop_trim: f32,
+
+    /// Converter current limit imposed by the input power budget
+    pub current_cap: Option<f32>,
+    /// Switched off by the input power budget
+    pub shed: bool,
+
+    pub protection: Protection,
+}
+
+impl ChannelState {
+    fn focus(&self, selected: bool) -> ChannelFocus {
+        match (self.protection.fault().is_some(), self.enable, selected) {
+            (true, _, true) => ChannelFocus::SelectedFault,
+            (true, _, false) => ChannelFocus::UnselectedFault,
+            (false, true, true) => ChannelFocus::SelectedActive,
+            (false, true, false) => ChannelFocus::UnselectedActive,
+            (false, false, true) => ChannelFocus::SelectedInactive,
+            (false, false, false) => ChannelFocus::UnselectedInactive,
+        }
+    }
 }
 
 impl Default for ChannelState {
     fn default() -> Self {
-        let target_limits = Limits {
-            voltage: 5.000,
-            current: 1.000,
-        };
+        let target_limits = Settings::default().default_setpoint;
         let max_limits = Limits {
             voltage: 20.000,
             current: 5.000,
@@ -174,6 +245,15 @@ impl Default for ChannelState {
             limits: VoltageCurrentWithSetter::new(max_limits, (0.2, 20.0), (0.0, 5.0)),
             set_select: Default::default(),
             readout: None,
+            statistics: Default::default(),
+            energy: Default::default(),
+            regulation: None,
+            region: None,
+            cable_trim: 0.0,
+            loop_trim: 0.0,
+            current_cap: None,
+            shed: false,
+            protection: Default::default(),
         }
     }
 }
@@ -185,12 +265,26 @@ pub enum ArrowsFunction {
     SetpointEdit,
 }
 
+/// Setpoint being edited, only applied to the channel on Enter
+struct StagedSetpoint {
+    channel: Channel,
+    values: VoltageCurrentWithSetter,
+}
+
 #[derive(Default)]
 struct InterfaceState {
     pub screen: Screen,
     pub selected_channel: Option<Channel>,
 
     pub arrows_function: ArrowsFunction,
+    pub staged: Option<StagedSetpoint>,
+    pub recovery_action: RecoveryAction,
+    /// Statistics or energy counters shown in place of the live readouts
+    pub readout_view: ReadoutView,
+
+    pub settings_cursor: usize,
+    pub settings_page: SettingsPage,
+    pub wizard: Option<Wizard>,
 }
 
 #[derive(Default)]
@@ -204,7 +298,7 @@ enum HardwareState {
 
     WaitingMainUi,
     Standby,
-    // Error,
+    Error(FaultReason),
 }
 
 #[derive(Default)]
@@ -212,7 +306,8 @@ enum Screen {
     #[default]
     Boot,
     Main,
-    // Settings,
+    Fault,
+    Settings,
 }
 
 impl App {
@@ -220,6 +315,7 @@ impl App {
         match event {
             AppEvent::Hardware(hw) => self.handle_hardware_event(hw),
             AppEvent::Interface(ui) => self.handle_interface_event(ui),
+            AppEvent::Remote(command) => self.handle_remote_event(command),
         }
     }
 
@@ -228,77 +324,360 @@ impl App {
             (HardwareState::PowerOn, HardwareEvent::PowerOn) => {
                 self.hardware_state = HardwareState::WaitingForPowerDelivery;
 
-                AppTaskBuilder::new()
+                // A retried init keeps the live state, setpoints included
+                let load_task = match self.config_loaded {
+                    true => AppTaskBuilder::new(),
+                    false => AppTaskBuilder::new().hardware(HardwareTask::LoadConfig),
+                };
+
+                load_task
                     .hardware(HardwareTask::EnablePowerDelivery)
                     .display(DisplayTask::SetupSplash)
+                    .extend(self.brightness_task())
                     .build()
             }
+            (_, HardwareEvent::ConfigLoaded(config))
+                if matches!(self.interface_state.screen, Screen::Boot) && !self.config_loaded =>
+            {
+                self.config_loaded = true;
+                self.restore_config_task(config).build()
+            }
             (
                 HardwareState::WaitingForPowerDelivery,
-                HardwareEvent::PowerDeliveryReady(power_type),
+                HardwareEvent::PowerDeliveryReady(result),
             ) => {
-                self.hardware_state = HardwareState::WaitingForSense;
-                self.power_type = power_type;
+                self.power_delivery_test = Some(result.map(|_| ()));
+
+                let next_task = match result {
+                    Ok(power_type) => {
+                        self.hardware_state = HardwareState::WaitingForSense;
+                        self.power_type = power_type;
+                        HardwareTask::EnableSense
+                    }
+                    Err(fault) => HardwareTask::DelayedHardwareEvent(
+                        Duration::from_millis(500),
+                        HardwareEvent::Fault(FaultReason::SelfTest(fault)),
+                    ),
+                };
 
                 AppTaskBuilder::new()
-                    .hardware(HardwareTask::EnableSense)
-                    .display(DisplayTask::ConfirmPowerDelivery(power_type))
+                    .hardware(next_task)
+                    .display(DisplayTask::ConfirmPowerDelivery(result))
                     .build()
             }
             (HardwareState::WaitingForSense, HardwareEvent::SenseReady(result)) => {
-                self.hardware_state = HardwareState::WaitingForConverter;
+                self.sense_test = Some(result);
+
+                let next_task = match result.first_fault() {
+                    None => {
+                        self.hardware_state = HardwareState::WaitingForConverter;
+                        HardwareTask::EnableConverter
+                    }
+                    Some(fault) => HardwareTask::DelayedHardwareEvent(
+                        Duration::from_millis(500),
+                        HardwareEvent::Fault(FaultReason::SelfTest(fault)),
+                    ),
+                };
 
                 AppTaskBuilder::new()
-                    .hardware(HardwareTask::EnableConverter)
+                    .hardware(next_task)
                     .display(DisplayTask::ConfirmSense(result))
                     .build()
             }
             (HardwareState::WaitingForConverter, HardwareEvent::ConverterReady(result)) => {
-                self.hardware_state = HardwareState::WaitingMainUi;
+                self.converter_test = Some(result);
+
+                let next_event = match result.first_fault() {
+                    None => {
+                        self.hardware_state = HardwareState::WaitingMainUi;
+                        HardwareEvent::StartMainInterface
+                    }
+                    Some(fault) => HardwareEvent::Fault(FaultReason::SelfTest(fault)),
+                };
 
                 AppTaskBuilder::new()
+                    .extend(self.converter_mode_task(Channel::A))
+                    .extend(self.converter_mode_task(Channel::B))
+                    .extend(self.cable_compensation_task(Channel::A))
+                    .extend(self.cable_compensation_task(Channel::B))
+                    .extend(self.sense_alert_limits_task())
                     .hardware(HardwareTask::DelayedHardwareEvent(
                         Duration::from_millis(500),
-                        HardwareEvent::StartMainInterface,
+                        next_event,
                     ))
                     .display(DisplayTask::ConfirmConverter(result))
                     .build()
             }
             (HardwareState::WaitingMainUi, HardwareEvent::StartMainInterface) => {
                 self.hardware_state = HardwareState::Standby;
-                self.interface_state.screen = Screen::Main;
 
-                let power_type = self.power_type;
-                let (ch_a_limit, ch_b_limit) = self.get_current_set();
+                let mut main_task = self.initialize_converters_task();
+                if self.sense_ok(Channel::A) || self.sense_ok(Channel::B) {
+                    main_task = main_task.hardware(HardwareTask::EnableReadoutLoop);
+                }
 
-                self.initialize_converters_task()
-                    .hardware(HardwareTask::EnableReadoutLoop)
-                    .display(DisplayTask::SetupMain(power_type, ch_a_limit, ch_b_limit))
-                    .build()
+                main_task.extend(self.setup_main_task()).build()
+            }
+            (HardwareState::Error(_), HardwareEvent::Fault(reason)) => {
+                warn!("fault {} while already in error state", reason);
+                None
             }
-            (HardwareState::Standby, HardwareEvent::ReadoutAcquired(channel, readout)) => {
-                let current_readout = match channel {
-                    Channel::A => &mut self.ch_a.readout,
-                    Channel::B => &mut self.ch_b.readout,
+            (_, HardwareEvent::Fault(reason)) => self.enter_fault_task(reason).build(),
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::ReadoutAcquired(channel, readout),
+            ) => {
+                let in_standby = matches!(self.hardware_state, HardwareState::Standby);
+                let on_main = matches!(self.interface_state.screen, Screen::Main);
+                let behaviour = self.settings.protection;
+
+                let hold = self.settings.energy_hold;
+                let state = self.channel_state_mut(channel);
+                state.readout = Some(readout);
+                match state.enable {
+                    true => {
+                        state.statistics.add(&readout);
+                        state.energy.add(&readout, hold);
+                    }
+                    false => state.energy.pause(),
+                }
+
+                let readout_task = match on_main {
+                    true => self.readout_display_task(channel, readout),
+                    false => AppTaskBuilder::new(),
+                };
+
+                let current_state = match channel {
+                    Channel::A => &mut self.ch_a,
+                    Channel::B => &mut self.ch_b,
+                };
+
+                let trip = match current_state.enable && behaviour != ProtectionBehaviour::Off {
+                    true => {
+                        let limits = current_state.limits.get_limits();
+                        current_state.protection.check(&readout, &limits)
+                    }
+                    false => None,
                 };
-                *current_readout = Some(readout);
 
-                AppTaskBuilder::display_task(DisplayTask::UpdateReadout(channel, readout))
+                match (trip, in_standby) {
+                    (Some(trip), _) => {
+                        warn!("channel {} protection trip {}", channel, trip);
+                        current_state.enable = false;
+
+                        let disable_task = AppTaskBuilder::new()
+                            .hardware(HardwareTask::UpdateConverterState(channel, false));
+
+                        match (behaviour, in_standby, on_main) {
+                            (ProtectionBehaviour::FaultScreen, true, _) => disable_task
+                                .extend(
+                                    self.enter_fault_task(FaultReason::Protection(channel, trip)),
+                                )
+                                .build(),
+                            (_, _, true) => disable_task
+                                .extend(readout_task)
+                                .display(DisplayTask::UpdateProtection(channel, Some(trip)))
+                                .extend(self.channel_focus_task())
+                                .build(),
+                            _ => disable_task.build(),
+                        }
+                    }
+                    (None, true) => {
+                        let mut task = readout_task;
+
+                        // Sample the converter STATUS right behind every readout of a live output
+                        if current_state.enable {
+                            task = task.hardware(HardwareTask::ReadConverterStatus(channel));
+                        } else if on_main {
+                            if current_state.regulation.take().is_some() {
+                                task = task.display(DisplayTask::UpdateRegulation(channel, None));
+                            }
+                            if current_state.region.take().is_some() {
+                                task = task.display(DisplayTask::UpdateRegion(channel, None));
+                            }
+                        } else {
+                            current_state.regulation = None;
+                            current_state.region = None;
+                        }
+
+                        task.extend(self.output_trim_task(channel))
+                            .extend(self.budget_task())
+                            .build()
+                    }
+                    (None, _) => None,
+                }
             }
+            (HardwareState::Standby, HardwareEvent::ConverterStatus(channel, status)) => {
+                // Polling STATUS clears the flags before ~INT gets to report them
+                match status.fault() {
+                    Some(fault @ (ConverterFault::ShortCircuit | ConverterFault::OverVoltage)) => {
+                        return self.converter_fault_task(channel, fault).build();
+                    }
+                    _ => {}
+                }
+
+                let on_main = matches!(self.interface_state.screen, Screen::Main);
+
+                let current_state = match channel {
+                    Channel::A => &mut self.ch_a,
+                    Channel::B => &mut self.ch_b,
+                };
+
+                let (true, Some(readout)) = (current_state.enable, current_state.readout) else {
+                    return None;
+                };
+
+                let mut task = AppTaskBuilder::new();
+
+                if current_state.region != status.region {
+                    debug!("channel {} region {}", channel, status.region);
+                    current_state.region = status.region;
+                    if on_main {
+                        task = task.display(DisplayTask::UpdateRegion(channel, status.region));
+                    }
+                }
+
+                let target = current_state.target.get_limits();
+                let mode = regulation_mode(&readout, &target, &status);
+                if current_state.regulation != Some(mode) {
+                    info!("channel {} regulation {}", channel, mode);
+                    current_state.regulation = Some(mode);
+                    if on_main {
+                        task = task.display(DisplayTask::UpdateRegulation(channel, Some(mode)));
+                    }
+                }
+
+                task.build()
+            }
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::ConverterFault(channel, fault),
+            ) => self.converter_fault_task(channel, fault).build(),
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::SenseAlert(channel),
+            ) => self.sense_alert_task(channel).build(),
+            // Nothing tripped, the output comes back once the converter is restored
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::SenseAlertSpurious(channel),
+            ) => AppTaskBuilder::new()
+                .hardware(HardwareTask::RestartConverter(channel))
+                .build(),
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::ConverterRestarted(channel),
+            ) => self.converter_restarted_task(channel).build(),
+            (
+                HardwareState::Standby | HardwareState::Error(_),
+                HardwareEvent::CaptureDone(channel, len),
+            ) => self.capture_done_task(channel, len).build(),
             _ => None,
         }
     }
 
+    /// Shut a channel down on a fault its converter flagged. The converter
+    /// has already acted on it, so this applies even with protection off.
+    fn converter_fault_task(&mut self, channel: Channel, fault: ConverterFault) -> AppTaskBuilder {
+        let trip = match fault {
+            ConverterFault::ShortCircuit => ProtectionTrip::ShortCircuit,
+            ConverterFault::OverVoltage => ProtectionTrip::OverVoltage,
+            // IOUT_LIMIT is the channel's current setpoint: hitting it is CC operation
+            ConverterFault::OverCurrent => return AppTaskBuilder::new(),
+        };
+
+        // The wizard drives its output with `enable` clear, the output is live all the same
+        let calibrating = self.calibrating(channel);
+        let state = self.channel_state_mut(channel);
+        if !(state.enable || calibrating) || !state.protection.latch(trip) {
+            return AppTaskBuilder::new();
+        }
+
+        warn!("channel {} converter fault {}", channel, fault);
+        state.enable = false;
+
+        AppTaskBuilder::new()
+            .hardware(HardwareTask::UpdateConverterState(channel, false))
+            .extend(self.abort_calibration_task(channel))
+            .extend(self.protection_trip_task(channel, trip))
+    }
+
+    /// Catch up with an over-current the INA226 alert already shut down by
+    /// pulling the converter's EN low, which also reset the converter
+    fn sense_alert_task(&mut self, channel: Channel) -> AppTaskBuilder {
+        let restart_task =
+            AppTaskBuilder::new().hardware(HardwareTask::RestartConverter(channel));
+
+        let trip = ProtectionTrip::OverCurrent;
+        let state = self.channel_state_mut(channel);
+        let tripped = state.enable && state.protection.latch(trip);
+        state.enable = false;
+
+        match tripped {
+            true => restart_task.extend(self.protection_trip_task(channel, trip)),
+            false => restart_task,
+        }
+    }
+
+    /// Surface a latched trip the way the protection setting asks for
+    fn protection_trip_task(&mut self, channel: Channel, trip: ProtectionTrip) -> AppTaskBuilder {
+        let in_standby = matches!(self.hardware_state, HardwareState::Standby);
+        let on_main = matches!(self.interface_state.screen, Screen::Main);
+
+        match (self.settings.protection, in_standby, on_main) {
+            (ProtectionBehaviour::FaultScreen, true, _) => {
+                self.enter_fault_task(FaultReason::Protection(channel, trip))
+            }
+            (_, _, true) => AppTaskBuilder::new()
+                .display(DisplayTask::UpdateProtection(channel, Some(trip)))
+                .extend(self.channel_focus_task()),
+            _ => AppTaskBuilder::new(),
+        }
+    }
+
+    /// Restore what a converter lost to the reset of a sense alert, turning
+    /// the output back on when the alert left it enabled
+    fn converter_restarted_task(&self, channel: Channel) -> AppTaskBuilder {
+        let task = self
+            .converter_mode_task(channel)
+            .extend(self.cable_compensation_task(channel))
+            .extend(self.update_converter_task(channel));
+
+        match self.channel_state(channel).enable {
+            true => task.extend(self.output_state_task(channel, true)),
+            false => task,
+        }
+    }
+
     fn handle_interface_event(&mut self, event: InterfaceEvent) -> Option<AppTask> {
+        match self.interface_state.screen {
+            Screen::Fault => return self.handle_fault_interface_event(event),
+            Screen::Settings => return self.handle_settings_interface_event(event),
+            _ => {}
+        }
+
         match event {
             InterfaceEvent::ButtonSettings(change) => match change {
-                Change::Pressed => self
-                    .current_confirm_state_button_task(Some(FunctionButton::Settings))
-                    .build(),
+                Change::Pressed => match self.interface_state.screen {
+                    Screen::Main => self.enter_settings_task().build(),
+                    _ => self
+                        .current_confirm_state_button_task(Some(FunctionButton::Settings))
+                        .build(),
+                },
                 Change::Released => self.return_current_button_state_task().build(),
             },
             InterfaceEvent::ButtonSwitch(change) => match change {
                 Change::Pressed => {
+                    if let ArrowsFunction::SetpointEdit = self.interface_state.arrows_function {
+                        self.discard_setpoint_edit();
+                        return self
+                            .setpoints_task()
+                            .extend(
+                                self.current_confirm_state_button_task(Some(FunctionButton::Switch)),
+                            )
+                            .build();
+                    }
+
                     self.set_state = match self.set_state {
                         SetState::Set => SetState::Limits,
                         SetState::Limits => SetState::Set,
@@ -328,10 +707,21 @@ impl App {
 
                     self.interface_state.arrows_function =
                         match self.interface_state.arrows_function {
-                            ArrowsFunction::Navigation => ArrowsFunction::SetpointEdit,
+                            ArrowsFunction::Navigation => {
+                                self.interface_state.staged = Some(StagedSetpoint {
+                                    channel,
+                                    values: self.current_set_mut(channel).clone(),
+                                });
+                                ArrowsFunction::SetpointEdit
+                            }
                             ArrowsFunction::SetpointEdit => {
+                                if let Some(staged) = self.interface_state.staged.take() {
+                                    *self.current_set_mut(staged.channel) = staged.values;
+                                }
                                 converter_task = converter_task
-                                    .extend(self.update_converter_task(channel));
+                                    .extend(self.update_converter_task(channel))
+                                    .extend(self.sense_alert_limits_task())
+                                    .extend(self.save_config_task());
                                 ArrowsFunction::Navigation
                             }
                         };
@@ -402,6 +792,17 @@ impl App {
                 }
             },
             InterfaceEvent::ButtonChannel(event_channel) => {
+                // Mid-edit, a channel button only backs out of the staged setpoint
+                if let ArrowsFunction::SetpointEdit = self.interface_state.arrows_function {
+                    self.discard_setpoint_edit();
+                    return self
+                        .shift_channel_focus_task(event_channel)
+                        .extend(self.current_confirm_state_button_task(None))
+                        .build();
+                }
+
+                let available = self.channel_available(event_channel);
+
                 let current_state = match event_channel {
                     Channel::A => &mut self.ch_a,
                     Channel::B => &mut self.ch_b,
@@ -412,17 +813,35 @@ impl App {
                 let mut converter_update_task = AppTaskBuilder::new();
 
                 let mut set_value_override = false;
-                if selected_channel.as_ref() == Some(&event_channel) {
-                    current_state.enable = !current_state.enable;
-                    converter_update_task = converter_update_task.hardware(
-                        HardwareTask::UpdateConverterState(event_channel, current_state.enable),
-                    );
+                let mut switched = None;
+                if selected_channel.as_ref() == Some(&event_channel)
+                    && current_state.protection.acknowledge()
+                {
+                    // A latched trip must be acknowledged before the output can be re-enabled
+                    converter_update_task = converter_update_task
+                        .display(DisplayTask::UpdateProtection(event_channel, None));
+                } else if selected_channel.as_ref() == Some(&event_channel) {
+                    // Outputs stay off on channels that failed their self-test
+                    if available {
+                        current_state.enable = !current_state.enable;
+                        switched = Some(current_state.enable);
+
+                        if current_state.enable && core::mem::take(&mut current_state.shed) {
+                            converter_update_task = converter_update_task
+                                .display(DisplayTask::UpdateProtection(event_channel, None));
+                        }
+                    }
                 } else {
                     self.interface_state.arrows_function = ArrowsFunction::Navigation;
                     set_value_override = true;
                 }
                 *selected_channel = Some(event_channel);
 
+                if let Some(enable) = switched {
+                    converter_update_task =
+                        converter_update_task.extend(self.output_state_task(event_channel, enable));
+                }
+
                 if set_value_override {
                     self.shift_channel_focus_task(event_channel)
                         .extend(self.current_confirm_state_button_task(None))
@@ -432,9 +851,158 @@ impl App {
                 }
                 .build()
             }
+            // The readout view only exists on the main screen
+            InterfaceEvent::ButtonChord(_)
+                if !matches!(self.interface_state.screen, Screen::Main) =>
+            {
+                None
+            }
+            InterfaceEvent::ButtonChord(Chord::UpDown) => self.reset_readout_view_task().build(),
+            InterfaceEvent::ButtonChord(Chord::LeftRight) => self.cycle_readout_view_task().build(),
+        }
+    }
+
+    fn handle_fault_interface_event(&mut self, event: InterfaceEvent) -> Option<AppTask> {
+        let action = &mut self.interface_state.recovery_action;
+
+        match event {
+            InterfaceEvent::ButtonUp => {
+                *action = action.prev();
+                AppTaskBuilder::display_task(DisplayTask::UpdateRecoveryAction(*action))
+            }
+            InterfaceEvent::ButtonDown => {
+                *action = action.next();
+                AppTaskBuilder::display_task(DisplayTask::UpdateRecoveryAction(*action))
+            }
+            InterfaceEvent::ButtonEnter(Change::Pressed) => {
+                let action = *action;
+                self.recovery_task(action).build()
+            }
+            _ => None,
+        }
+    }
+
+    fn enter_fault_task(&mut self, reason: FaultReason) -> AppTaskBuilder {
+        error!("entering fault state: {}", reason);
+
+        self.hardware_state = HardwareState::Error(reason);
+        self.interface_state.screen = Screen::Fault;
+        self.discard_setpoint_edit();
+        self.interface_state.recovery_action = RecoveryAction::default();
+
+        self.cancel_calibration_task()
+            .extend(self.cancel_capture_task())
+            .display(DisplayTask::SetupFault(
+                reason,
+                self.interface_state.recovery_action,
+            ))
+    }
+
+    fn recovery_task(&mut self, action: RecoveryAction) -> AppTaskBuilder {
+        let reason = match self.hardware_state {
+            HardwareState::Error(reason) => reason,
+            _ => return AppTaskBuilder::new(),
+        };
+
+        match action {
+            RecoveryAction::RetryInit => {
+                let disable_task = self.disable_outputs_task();
+
+                self.power_delivery_test = None;
+                self.sense_test = None;
+                self.converter_test = None;
+                self.hardware_state = HardwareState::PowerOn;
+                self.interface_state.screen = Screen::Boot;
+
+                disable_task.hardware(HardwareTask::DelayedHardwareEvent(
+                    Duration::from_millis(0),
+                    HardwareEvent::PowerOn,
+                ))
+            }
+            RecoveryAction::ContinueDegraded => self.resume_task(reason),
+            RecoveryAction::DisableOutputs => {
+                self.disable_outputs_task().extend(self.resume_task(reason))
+            }
+        }
+    }
+
+    /// Pick the boot sequence back up after the step that failed, or return
+    /// to the main interface for runtime faults.
+    fn resume_task(&mut self, reason: FaultReason) -> AppTaskBuilder {
+        match reason {
+            FaultReason::SelfTest(fault @ ChipFault { chip: Chip::Stusb4500, .. }) => {
+                self.hardware_state = HardwareState::WaitingForSense;
+                self.interface_state.screen = Screen::Boot;
+                self.power_type = PowerType::default();
+
+                AppTaskBuilder::new()
+                    .display(DisplayTask::SetupSplash)
+                    .display(DisplayTask::ConfirmPowerDelivery(Err(fault)))
+                    .hardware(HardwareTask::EnableSense)
+            }
+            FaultReason::SelfTest(ChipFault { chip: Chip::Ina226(_), .. }) => {
+                self.hardware_state = HardwareState::WaitingForConverter;
+                self.interface_state.screen = Screen::Boot;
+
+                AppTaskBuilder::new()
+                    .display(DisplayTask::SetupSplash)
+                    .display(DisplayTask::ConfirmPowerDelivery(Ok(self.power_type)))
+                    .display(DisplayTask::ConfirmSense(self.sense_test.unwrap_or_default()))
+                    .hardware(HardwareTask::EnableConverter)
+            }
+            _ => {
+                self.hardware_state = HardwareState::WaitingMainUi;
+
+                AppTaskBuilder::new().hardware(HardwareTask::DelayedHardwareEvent(
+                    Duration::from_millis(0),
+                    HardwareEvent::StartMainInterface,
+                ))
+            }
+        }
+    }
+
+    fn disable_outputs_task(&mut self) -> AppTaskBuilder {
+        self.ch_a.enable = false;
+        self.ch_b.enable = false;
+
+        let mut task = AppTaskBuilder::new();
+        for channel in [Channel::A, Channel::B] {
+            if self.converter_ok(channel) {
+                task = task.hardware(HardwareTask::UpdateConverterState(channel, false));
+            }
+        }
+
+        task
+    }
+
+    fn sense_ok(&self, channel: Channel) -> bool {
+        self.sense_test.is_some_and(|test| test.channel_ok(channel))
+    }
+
+    fn converter_ok(&self, channel: Channel) -> bool {
+        self.converter_test.is_some_and(|test| test.channel_ok(channel))
+    }
+
+    fn channel_state(&self, channel: Channel) -> &ChannelState {
+        match channel {
+            Channel::A => &self.ch_a,
+            Channel::B => &self.ch_b,
+        }
+    }
+
+    fn channel_state_mut(&mut self, channel: Channel) -> &mut ChannelState {
+        match channel {
+            Channel::A => &mut self.ch_a,
+            Channel::B => &mut self.ch_b,
         }
     }
 
+    /// Outputs may only be enabled on channels whose sense and converter
+    /// chips both passed their self-test.
+    fn channel_available(&self, channel: Channel) -> bool {
+        self.sense_ok(channel) && self.converter_ok(channel)
+    }
+
     pub fn get_current_set(&mut self) -> (Limits, Limits) {
         match self.set_state {
             SetState::Set => (self.ch_a.target.get_limits(), self.ch_b.target.get_limits()),
@@ -471,21 +1039,31 @@ impl App {
         }
     }
 
-    fn get_select_precision_mut(&mut self) -> Option<&mut WithPrecision> {
-        let (ch_a_set, ch_b_set) = match self.set_state {
-            SetState::Set => (&mut self.ch_a.target, &mut self.ch_b.target),
-            SetState::Limits => (&mut self.ch_a.limits, &mut self.ch_b.limits),
-        };
+    fn current_set_mut(&mut self, channel: Channel) -> &mut VoltageCurrentWithSetter {
+        let set_state = self.set_state;
+        let state = self.channel_state_mut(channel);
 
-        let (ch_set, set_select) = match self.interface_state.selected_channel {
-            Some(Channel::A) => (ch_a_set, self.ch_a.set_select),
-            Some(Channel::B) => (ch_b_set, self.ch_b.set_select),
-            _ => return None,
-        };
+        match set_state {
+            SetState::Set => &mut state.target,
+            SetState::Limits => &mut state.limits,
+        }
+    }
+
+    /// Leave setpoint editing, dropping whatever was staged
+    fn discard_setpoint_edit(&mut self) {
+        self.interface_state.arrows_function = ArrowsFunction::Navigation;
+        self.interface_state.staged = None;
+    }
+
+    /// Edits go to the staged copy, the applied setpoint stays untouched until Enter
+    fn get_select_precision_mut(&mut self) -> Option<&mut WithPrecision> {
+        let channel = self.interface_state.staged.as_ref()?.channel;
+        let set_select = self.channel_state(channel).set_select;
+        let staged = &mut self.interface_state.staged.as_mut()?.values;
 
         match set_select {
-            SetSelect::Voltage => Some(&mut ch_set.voltage),
-            SetSelect::Current => Some(&mut ch_set.current),
+            SetSelect::Voltage => Some(&mut staged.voltage),
+            SetSelect::Current => Some(&mut staged.current),
         }
     }
 
@@ -499,9 +1077,25 @@ impl App {
     }
 
     pub fn setpoints_task(&mut self) -> AppTaskBuilder {
-        let (ch_a_set, ch_b_set) = self.get_current_set();
+        let (mut ch_a_set, mut ch_b_set) = self.get_current_set();
         let (ch_a_select, ch_b_select) = self.get_current_select_set();
 
+        // Show the staged values, marking those not yet applied to the channel
+        let (mut ch_a_pending, mut ch_b_pending) = ([false; 2], [false; 2]);
+        if let Some(staged) = &self.interface_state.staged {
+            let (applied, pending) = match staged.channel {
+                Channel::A => (&mut ch_a_set, &mut ch_a_pending),
+                Channel::B => (&mut ch_b_set, &mut ch_b_pending),
+            };
+            let values = staged.values.get_limits();
+
+            *pending = [
+                values.voltage != applied.voltage,
+                values.current != applied.current,
+            ];
+            *applied = values;
+        }
+
         let confirm_state = self.get_confirm_state();
         let select_precision = match self.interface_state.arrows_function {
             ArrowsFunction::Navigation => None,
@@ -524,6 +1118,7 @@ impl App {
             .display(DisplayTask::UpdateSetpoint(
                 Channel::A,
                 ch_a_set,
+                ch_a_pending,
                 ch_a_select,
                 confirm_state,
                 select_precision,
@@ -531,6 +1126,7 @@ impl App {
             .display(DisplayTask::UpdateSetpoint(
                 Channel::B,
                 ch_b_set,
+                ch_b_pending,
                 ch_b_select,
                 confirm_state,
                 select_precision,
@@ -538,29 +1134,61 @@ impl App {
     }
 
     pub fn shift_channel_focus_task(&mut self, channel: Channel) -> AppTaskBuilder {
-        let (current_state, other_state) = match channel {
-            Channel::A => (&self.ch_a, &self.ch_b),
-            Channel::B => (&self.ch_b, &self.ch_a),
-        };
+        self.interface_state.selected_channel = Some(channel);
 
-        let other_focus = if other_state.enable {
-            ChannelFocus::UnselectedActive
-        } else {
-            ChannelFocus::UnselectedInactive
-        };
-        let current_focus = if current_state.enable {
-            ChannelFocus::SelectedActive
-        } else {
-            ChannelFocus::SelectedInactive
-        };
+        self.setpoints_task().extend(self.channel_focus_task())
+    }
 
-        let (focus_a, focus_b) = match channel {
-            Channel::A => (current_focus, other_focus),
-            Channel::B => (other_focus, current_focus),
-        };
+    /// Redraw the whole main interface from the current state
+    pub fn setup_main_task(&mut self) -> AppTaskBuilder {
+        self.interface_state.screen = Screen::Main;
+
+        let power_type = self.power_type;
+        let (ch_a_limit, ch_b_limit) = self.get_current_set();
 
-        self.setpoints_task()
-            .display(DisplayTask::UpdateChannelFocus(focus_a, focus_b))
+        AppTaskBuilder::new()
+            .display(DisplayTask::SetupMain(power_type, ch_a_limit, ch_b_limit))
+            .extend(self.setpoints_task())
+            .extend(self.channel_focus_task())
+            .extend(self.channel_status_task())
+            .extend(self.budget_display_task())
+    }
+
+    pub fn channel_status_task(&self) -> AppTaskBuilder {
+        let mut task = AppTaskBuilder::new();
+
+        for channel in [Channel::A, Channel::B] {
+            let state = match channel {
+                Channel::A => &self.ch_a,
+                Channel::B => &self.ch_b,
+            };
+
+            if !self.channel_available(channel) {
+                task = task.display(DisplayTask::UpdateChannelOffline(channel));
+                continue;
+            }
+
+            if let Some(trip) = state.protection.fault() {
+                task = task.display(DisplayTask::UpdateProtection(channel, Some(trip)));
+            } else if state.shed {
+                task = task.display(DisplayTask::UpdateChannelShed(channel));
+            }
+            task = task.display(DisplayTask::UpdateRegulation(channel, state.regulation));
+            if state.region.is_some() {
+                task = task.display(DisplayTask::UpdateRegion(channel, state.region));
+            }
+        }
+
+        task
+    }
+
+    pub fn channel_focus_task(&self) -> AppTaskBuilder {
+        let selected_channel = self.interface_state.selected_channel;
+
+        let focus_a = self.ch_a.focus(selected_channel == Some(Channel::A));
+        let focus_b = self.ch_b.focus(selected_channel == Some(Channel::B));
+
+        AppTaskBuilder::new().display(DisplayTask::UpdateChannelFocus(focus_a, focus_b))
     }
 
     pub fn current_confirm_state_button_task(
@@ -580,21 +1208,62 @@ impl App {
         self.current_confirm_state_button_task(function_button)
     }
 
+    /// Arm the INA226 alerts with each channel's OCP limit
+    fn sense_alert_limits_task(&self) -> AppTaskBuilder {
+        let limit = |channel| {
+            let armed = self.settings.protection != ProtectionBehaviour::Off
+                && self.sense_ok(channel)
+                && !self.calibrating(channel);
+            armed.then(|| self.channel_state(channel).limits.current.value())
+        };
+
+        AppTaskBuilder::new().hardware(HardwareTask::UpdateSenseAlert([
+            limit(Channel::A),
+            limit(Channel::B),
+        ]))
+    }
+
     pub fn update_converter_task(&self, channel: Channel) -> AppTaskBuilder {
-        let target = match channel {
-            Channel::A => &self.ch_a.target,
-            Channel::B => &self.ch_b.target,
+        if !self.converter_ok(channel) || self.calibrating(channel) {
+            return AppTaskBuilder::new();
+        }
+
+        let state = self.channel_state(channel);
+        let target = &state.target;
+
+        // The budget cap only ever lowers the requested current limit
+        let current = match state.current_cap {
+            Some(cap) => target.current.value().min(cap),
+            None => target.current.value(),
+        };
+
+        // Live outputs ramp to a new setpoint when the channel has a ramp set
+        let voltage = self.output_voltage(channel);
+        let voltage_task = match (state.enable, self.settings.ramp(channel)) {
+            (true, Some(rate)) => HardwareTask::RampConverterVoltage(channel, voltage, rate),
+            _ => HardwareTask::UpdateConverterVoltage(channel, voltage),
         };
 
         AppTaskBuilder::new()
-            .hardware(HardwareTask::UpdateConverterVoltage(
-                channel,
-                target.voltage.value(),
-            ))
-            .hardware(HardwareTask::UpdateConverterCurrent(
-                channel,
-                target.current.value(),
-            ))
+            .hardware(voltage_task)
+            .hardware(HardwareTask::UpdateConverterCurrent(channel, current))
+    }
+
+    /// Switch an output, bringing it up from the lowest voltage when it ramps
+    fn output_state_task(&self, channel: Channel, enable: bool) -> AppTaskBuilder {
+        let task = AppTaskBuilder::new();
+
+        match (enable, self.settings.ramp(channel)) {
+            (true, Some(rate)) => task
+                .hardware(HardwareTask::UpdateConverterVoltage(channel, RAMP_START_VOLTAGE))
+                .hardware(HardwareTask::UpdateConverterState(channel, true))
+                .hardware(HardwareTask::RampConverterVoltage(
+                    channel,
+                    self.output_voltage(channel),
+                    rate,
+                )),
+            _ => task.hardware(HardwareTask::UpdateConverterState(channel, enable)),
+        }
     }
 
     pub fn initialize_converters_task(&self) -> AppTaskBuilder {
@@ -0,0 +1,190 @@
+use defmt::*;
+
+use crate::app::{App, Screen};
+use crate::hal::event::{
+    AppTaskBuilder, BudgetState, Channel, DisplayTask, HardwareTask, PowerType,
+};
+
+/// Estimated TPS55289 efficiency over the usual operating range
+const CONVERTER_EFFICIENCY: f32 = 0.90;
+/// MCU, display backlight and LEDs
+const QUIESCENT_POWER: f32 = 0.5; // W
+
+const WARNING_RATIO: f32 = 0.85;
+/// Usage must fall this far before current caps are lifted again
+const RELEASE_RATIO: f32 = 0.75;
+/// Caps aim slightly under the budget so that they are not hit right away
+const CAP_MARGIN: f32 = 0.95;
+const CAP_MIN: f32 = 0.1; // A
+
+/// What to do once both channels draw more than the input can deliver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Scale down both converters' current limits
+    #[default]
+    LimitCurrent,
+    /// Switch off channel A, then limit current on channel B
+    ShedA,
+    /// Switch off channel B, then limit current on channel A
+    ShedB,
+    Off,
+}
+
+impl BudgetPolicy {
+    pub fn next(self) -> Self {
+        match self {
+            BudgetPolicy::LimitCurrent => BudgetPolicy::ShedA,
+            BudgetPolicy::ShedA => BudgetPolicy::ShedB,
+            BudgetPolicy::ShedB => BudgetPolicy::Off,
+            BudgetPolicy::Off => BudgetPolicy::LimitCurrent,
+        }
+    }
+
+    pub fn prev(self) -> Self {
+        match self {
+            BudgetPolicy::LimitCurrent => BudgetPolicy::Off,
+            BudgetPolicy::ShedA => BudgetPolicy::LimitCurrent,
+            BudgetPolicy::ShedB => BudgetPolicy::ShedA,
+            BudgetPolicy::Off => BudgetPolicy::ShedB,
+        }
+    }
+
+    fn shed_channel(self) -> Option<Channel> {
+        match self {
+            BudgetPolicy::ShedA => Some(Channel::A),
+            BudgetPolicy::ShedB => Some(Channel::B),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Budget {
+    state: BudgetState,
+    /// % of the input budget, as last shown
+    percent: u8,
+}
+
+impl App {
+    /// Input power the source can deliver
+    fn budget_available(&self) -> f32 {
+        match self.power_type {
+            PowerType::PowerDelivery(limits) | PowerType::Standard(limits) => {
+                limits.voltage * limits.current
+            }
+        }
+    }
+
+    /// Estimated input power drawn by both channels
+    fn budget_usage(&self) -> f32 {
+        let output: f32 = [&self.ch_a, &self.ch_b]
+            .iter()
+            .filter(|state| state.enable)
+            .filter_map(|state| state.readout)
+            .map(|readout| readout.power.max(0.0))
+            .sum();
+
+        output / CONVERTER_EFFICIENCY + QUIESCENT_POWER
+    }
+
+    /// Re-evaluate the budget after a readout and act on it
+    pub(super) fn budget_task(&mut self) -> AppTaskBuilder {
+        let available = self.budget_available();
+        if available <= 0.0 {
+            return AppTaskBuilder::new();
+        }
+
+        let ratio = self.budget_usage() / available;
+        let state = match ratio {
+            r if r >= 1.0 => BudgetState::Exceeded,
+            r if r >= WARNING_RATIO => BudgetState::Warning,
+            _ => BudgetState::Normal,
+        };
+
+        let mut task = AppTaskBuilder::new();
+        let policy = self.settings.budget;
+
+        if state != self.budget.state {
+            match state {
+                BudgetState::Exceeded => warn!("input budget exceeded: {}W", ratio * available),
+                BudgetState::Warning => warn!("input budget at {}%", ratio * 100.0),
+                BudgetState::Normal => info!("input budget back to normal"),
+            }
+        }
+
+        if state == BudgetState::Exceeded && policy != BudgetPolicy::Off {
+            task = task.extend(self.budget_enforce_task(policy, ratio));
+        } else if ratio < RELEASE_RATIO {
+            task = task.extend(self.budget_release_task());
+        }
+
+        let percent = (ratio * 100.0).clamp(0.0, 255.0) as u8;
+        let changed = state != self.budget.state || percent != self.budget.percent;
+        self.budget.state = state;
+        self.budget.percent = percent;
+
+        if changed && matches!(self.interface_state.screen, Screen::Main) {
+            task = task.display(DisplayTask::UpdateBudget(percent, state));
+        }
+
+        task
+    }
+
+    fn budget_enforce_task(&mut self, policy: BudgetPolicy, ratio: f32) -> AppTaskBuilder {
+        if let Some(channel) = policy.shed_channel() {
+            let state = self.channel_state_mut(channel);
+            if state.enable {
+                warn!("shedding channel {}", channel);
+                state.enable = false;
+                state.shed = true;
+
+                let mut task = AppTaskBuilder::new()
+                    .hardware(HardwareTask::UpdateConverterState(channel, false));
+                if matches!(self.interface_state.screen, Screen::Main) {
+                    task = task
+                        .display(DisplayTask::UpdateChannelShed(channel))
+                        .extend(self.channel_focus_task());
+                }
+
+                // The next readout tells whether shedding was enough
+                return task;
+            }
+        }
+
+        let mut task = AppTaskBuilder::new();
+        for channel in [Channel::A, Channel::B] {
+            let state = self.channel_state_mut(channel);
+            let Some(readout) = state.readout.filter(|_| state.enable) else {
+                continue;
+            };
+
+            let cap = (readout.current * CAP_MARGIN / ratio).max(CAP_MIN);
+            let cap = state.current_cap.map_or(cap, |current| current.min(cap));
+            state.current_cap = Some(cap);
+
+            info!("channel {} current capped at {}A", channel, cap);
+            task = task.extend(self.update_converter_task(channel));
+        }
+
+        task
+    }
+
+    fn budget_release_task(&mut self) -> AppTaskBuilder {
+        let mut task = AppTaskBuilder::new();
+        for channel in [Channel::A, Channel::B] {
+            if self.channel_state_mut(channel).current_cap.take().is_some() {
+                info!("channel {} current cap lifted", channel);
+                task = task.extend(self.update_converter_task(channel));
+            }
+        }
+
+        task
+    }
+
+    pub(super) fn budget_display_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new().display(DisplayTask::UpdateBudget(
+            self.budget.percent,
+            self.budget.state,
+        ))
+    }
+}
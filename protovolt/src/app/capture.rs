@@ -0,0 +1,92 @@
+use defmt::*;
+
+use crate::app::{App, Screen, settings::SettingsPage};
+use crate::hal::event::{
+    AppTask, AppTaskBuilder, CaptureTrigger, Change, Channel, DisplayTask, HardwareTask,
+    InterfaceEvent,
+};
+
+impl App {
+    pub(super) fn handle_capture_event(
+        &mut self,
+        channel: Channel,
+        event: InterfaceEvent,
+    ) -> Option<AppTask> {
+        match event {
+            InterfaceEvent::ButtonEnter(Change::Pressed) => {
+                self.capture_output_task(channel).build()
+            }
+            InterfaceEvent::ButtonSettings(Change::Pressed) => self
+                .cancel_capture_task()
+                .extend(self.setup_settings_task())
+                .build(),
+            _ => None,
+        }
+    }
+
+    /// Arm a burst capture on a channel. One triggered by the output coming
+    /// on starts from the output off.
+    pub(super) fn start_capture_task(&mut self, channel: Channel) -> Option<AppTask> {
+        if !self.channel_available(channel) {
+            return None;
+        }
+
+        let trigger = self.settings.capture_trigger();
+        self.interface_state.settings_page = SettingsPage::Capture(channel);
+        self.capture_length = None;
+
+        let state = self.channel_state_mut(channel);
+        let task = match trigger == CaptureTrigger::OutputEnable && state.enable {
+            true => {
+                state.enable = false;
+                self.output_state_task(channel, false)
+            }
+            false => AppTaskBuilder::new(),
+        };
+
+        info!("capture {} armed", channel);
+        task.hardware(HardwareTask::StartCapture(channel, trigger))
+            .display(DisplayTask::SetupCapture(channel, trigger))
+            .build()
+    }
+
+    /// Switch the output on from the capture page, to trigger the capture
+    fn capture_output_task(&mut self, channel: Channel) -> AppTaskBuilder {
+        let state = self.channel_state_mut(channel);
+        if state.enable || state.protection.fault().is_some() {
+            return AppTaskBuilder::new();
+        }
+
+        state.enable = true;
+        state.shed = false;
+        self.output_state_task(channel, true)
+    }
+
+    pub(super) fn capture_done_task(&mut self, channel: Channel, len: usize) -> AppTaskBuilder {
+        self.capture_length = Some(len);
+
+        let on_page = matches!(self.interface_state.screen, Screen::Settings)
+            && matches!(
+                self.interface_state.settings_page,
+                SettingsPage::Capture(page_channel) if page_channel == channel
+            );
+
+        match on_page {
+            true => AppTaskBuilder::new().display(DisplayTask::ShowCapture),
+            false => AppTaskBuilder::new(),
+        }
+    }
+
+    /// Leave the capture page, stopping a capture still waiting on its trigger
+    pub(super) fn cancel_capture_task(&mut self) -> AppTaskBuilder {
+        if !matches!(self.interface_state.settings_page, SettingsPage::Capture(_)) {
+            return AppTaskBuilder::new();
+        }
+        self.interface_state.settings_page = SettingsPage::Menu;
+
+        match self.capture_length {
+            Some(_) => AppTaskBuilder::new(),
+            None => AppTaskBuilder::new().hardware(HardwareTask::CancelCapture),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::app::App;
+use crate::hal::event::{AppTaskBuilder, Channel, HardwareTask, RegulationMode};
+
+/// Smallest change in cable trim worth a converter write
+const TRIM_DEADBAND: f32 = 0.010; // V
+
+/// Output errors within this are left to the INA226's own accuracy
+const LOOP_DEADBAND: f32 = 0.005; // V
+const LOOP_DEADBAND_RATIO: f32 = 0.001;
+/// Largest correction the loop applies per readout
+const LOOP_STEP: f32 = 0.010; // V
+/// The loop only makes up for REF rounding and feedback divider tolerance
+const LOOP_TRIM_MAX: f32 = 0.200; // V
+/// Larger errors are a ramp, a transient or a load step rather than an offset
+const LOOP_CAPTURE: f32 = 0.500; // V
+
+impl App {
+    /// Program the converter CDC with the part of the cable resistance it reaches
+    pub(super) fn cable_compensation_task(&self, channel: Channel) -> AppTaskBuilder {
+        if !self.converter_ok(channel) {
+            return AppTaskBuilder::new();
+        }
+
+        let resistance = self.settings.hardware_compensation(channel);
+        AppTaskBuilder::new().hardware(HardwareTask::UpdateConverterCompensation(
+            channel, resistance,
+        ))
+    }
+
+    /// Follow the latest readout with the software corrections on a channel's
+    /// output voltage, reprogramming the converter once either has moved
+    pub(super) fn output_trim_task(&mut self, channel: Channel) -> AppTaskBuilder {
+        // The readout still shows the output from before a new cable trim
+        let moved = match self.update_cable_trim(channel) {
+            true => true,
+            false => self.update_loop_trim(channel),
+        };
+
+        match moved {
+            true => self.update_converter_task(channel),
+            false => AppTaskBuilder::new(),
+        }
+    }
+
+    /// Raise a live output by the drop over the cable resistance left to
+    /// software, returning whether the trim changed
+    fn update_cable_trim(&mut self, channel: Channel) -> bool {
+        let resistance = self.settings.software_compensation(channel);
+        let state = self.channel_state_mut(channel);
+
+        let trim = match (state.enable, state.readout, resistance) {
+            // Current sits at the limit in CC, a higher voltage would not move it
+            (true, _, Some(_)) if state.regulation == Some(RegulationMode::ConstantCurrent) => {
+                state.cable_trim
+            }
+            (true, Some(readout), Some(resistance)) => readout.current.max(0.0) * resistance,
+            _ => 0.0,
+        };
+
+        // Always settle back onto the plain target once the trim goes away
+        let settled = (trim - state.cable_trim).abs() < TRIM_DEADBAND
+            && (trim == 0.0) == (state.cable_trim == 0.0);
+        if settled {
+            return false;
+        }
+
+        state.cable_trim = trim;
+        true
+    }
+
+    /// Step the closed-loop correction towards the setpoint by comparing the
+    /// bus voltage readout with where the output should sit, returning
+    /// whether the correction changed
+    fn update_loop_trim(&mut self, channel: Channel) -> bool {
+        let closed_loop = self.settings.voltage_loop;
+        let cdc = self.settings.hardware_compensation(channel) as f32 / 1000.0;
+        let state = self.channel_state_mut(channel);
+
+        let (true, true, Some(readout)) = (closed_loop, state.enable, state.readout) else {
+            // Drop the correction once the loop opens
+            let moved = state.loop_trim != 0.0;
+            state.loop_trim = 0.0;
+            return moved;
+        };
+
+        // The voltage is not what is being regulated in CC
+        if state.regulation == Some(RegulationMode::ConstantCurrent) {
+            return false;
+        }
+
+        // Cable compensation intentionally lifts the output over the target
+        let current = readout.current.max(0.0);
+        let expected = state.target.voltage.value() + state.cable_trim + current * cdc;
+        let error = expected - readout.voltage;
+
+        let deadband = LOOP_DEADBAND + expected * LOOP_DEADBAND_RATIO;
+        if error.abs() < deadband || error.abs() > LOOP_CAPTURE {
+            return false;
+        }
+
+        let step = error.clamp(-LOOP_STEP, LOOP_STEP);
+        let trim = (state.loop_trim + step).clamp(-LOOP_TRIM_MAX, LOOP_TRIM_MAX);
+        if trim == state.loop_trim {
+            return false;
+        }
+
+        state.loop_trim = trim;
+        true
+    }
+
+    /// Voltage to program into a channel's converter: the target plus any
+    /// trims, which never lift it over the channel's voltage limit
+    pub(super) fn output_voltage(&self, channel: Channel) -> f32 {
+        let state = self.channel_state(channel);
+        let target = state.target.voltage.value();
+        let limit = state.limits.voltage.value().max(target);
+
+        (target + state.cable_trim + state.loop_trim).min(limit).clamp(0.2, 20.0)
+    }
+}
@@ -0,0 +1,485 @@
+use defmt::*;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use crate::app::budget::BudgetPolicy;
+use crate::app::settings::{ProtectionBehaviour, Settings};
+use crate::app::{App, ChannelState, WithPrecision};
+use crate::hal::calibration::{Calibration, Correction};
+use crate::hal::event::{AppTaskBuilder, ConverterMode, HardwareTask, Limits, SensePreset};
+use crate::hal::storage::{MAX_VALUE_SIZE, Storage, StorageError};
+
+/// Version of the record layouts below, stored with every record.
+///
+/// Fields are only ever appended to a record and the version bumped. The
+/// `decode_*` functions read the fields up to the stored version and require
+/// all of them, so the fields a record predates keep their defaults; a record
+/// from a newer firmware carries trailing fields that are ignored. Changing
+/// the meaning of an existing field instead needs a migration arm on the
+/// stored version in the matching `decode_*`.
+pub const SCHEMA_VERSION: u8 = 9;
+
+/// Schema the calibration records were introduced with
+const CALIBRATION_SCHEMA: u8 = 6;
+
+#[derive(Clone, Copy, Debug, Format)]
+enum Key {
+    Settings = 0,
+    ChannelA = 1,
+    ChannelB = 2,
+    CalibrationA = 3,
+    CalibrationB = 4,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelConfig {
+    pub target: Limits,
+    pub limits: Limits,
+    /// Edit cursor exponents: target voltage, target current, limit voltage, limit current
+    pub precision: [i8; 4],
+}
+
+/// Persisted state, each part absent when never saved or unreadable
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Config {
+    pub settings: Option<Settings>,
+    pub ch_a: Option<ChannelConfig>,
+    pub ch_b: Option<ChannelConfig>,
+    /// Only saved along when it changed
+    pub calibration: [Option<Calibration>; 2],
+}
+
+impl Config {
+    pub fn load<F>(storage: &mut Storage<F>) -> Self
+    where
+        F: NorFlash + ReadNorFlash,
+    {
+        Self {
+            settings: load_record(storage, Key::Settings, decode_settings),
+            ch_a: load_record(storage, Key::ChannelA, decode_channel),
+            ch_b: load_record(storage, Key::ChannelB, decode_channel),
+            calibration: [
+                load_record(storage, Key::CalibrationA, decode_calibration),
+                load_record(storage, Key::CalibrationB, decode_calibration),
+            ],
+        }
+    }
+
+    pub fn save<F>(&self, storage: &mut Storage<F>) -> Result<(), StorageError>
+    where
+        F: NorFlash + ReadNorFlash,
+    {
+        if let Some(settings) = &self.settings {
+            save_record(storage, Key::Settings, |w| encode_settings(w, settings))?;
+        }
+        if let Some(channel) = &self.ch_a {
+            save_record(storage, Key::ChannelA, |w| encode_channel(w, channel))?;
+        }
+        if let Some(channel) = &self.ch_b {
+            save_record(storage, Key::ChannelB, |w| encode_channel(w, channel))?;
+        }
+        for (key, calibration) in [Key::CalibrationA, Key::CalibrationB]
+            .into_iter()
+            .zip(&self.calibration)
+        {
+            if let Some(calibration) = calibration {
+                save_record(storage, key, |w| encode_calibration(w, calibration))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn load_record<F, T>(
+    storage: &mut Storage<F>,
+    key: Key,
+    decode: fn(&mut Reader, u8) -> Option<T>,
+) -> Option<T>
+where
+    F: NorFlash + ReadNorFlash,
+{
+    let mut buf = [0u8; MAX_VALUE_SIZE];
+    let (version, len) = match storage.get(key as u8, &mut buf) {
+        Ok(Some(record)) => record,
+        Ok(None) => return None,
+        Err(e) => {
+            warn!("config: failed to read {}: {}", key, e);
+            return None;
+        }
+    };
+
+    if version == 0 {
+        warn!("config: {} stored without a schema", key);
+        return None;
+    }
+    if version != SCHEMA_VERSION {
+        info!("config: {} stored with schema {}", key, version);
+    }
+
+    let value = decode(&mut Reader { data: &buf[..len] }, version);
+    if value.is_none() {
+        warn!("config: discarding unreadable {}", key);
+    }
+
+    value
+}
+
+fn save_record<F>(
+    storage: &mut Storage<F>,
+    key: Key,
+    encode: impl FnOnce(&mut Writer),
+) -> Result<(), StorageError>
+where
+    F: NorFlash + ReadNorFlash,
+{
+    let mut writer = Writer {
+        buf: [0u8; MAX_VALUE_SIZE],
+        len: 0,
+    };
+    encode(&mut writer);
+
+    storage.set(key as u8, SCHEMA_VERSION, &writer.buf[..writer.len])
+}
+
+fn encode_settings(w: &mut Writer, settings: &Settings) {
+    // Schema 1
+    w.u8(settings.display_brightness);
+    w.u8(settings.led_brightness);
+    w.limits(&settings.default_setpoint);
+    w.u8(match settings.protection {
+        ProtectionBehaviour::FaultScreen => 0,
+        ProtectionBehaviour::ChannelOnly => 1,
+        ProtectionBehaviour::Off => 2,
+    });
+
+    // Schema 2
+    w.u8(match settings.budget {
+        BudgetPolicy::LimitCurrent => 0,
+        BudgetPolicy::ShedA => 1,
+        BudgetPolicy::ShedB => 2,
+        BudgetPolicy::Off => 3,
+    });
+
+    // Schema 3
+    w.u8(settings.ramp_rate[0]);
+    w.u8(settings.ramp_rate[1]);
+
+    // Schema 4
+    for mode in &settings.converter_mode {
+        w.converter_mode(mode);
+    }
+
+    // Schema 5
+    for resistance in settings.cable_resistance {
+        w.u16(resistance);
+    }
+    for trim in settings.cable_trim {
+        w.u8(trim as u8);
+    }
+
+    // Schema 6
+    w.u8(settings.voltage_loop as u8);
+
+    // Schema 7
+    for preset in &settings.sense_preset {
+        w.sense_preset(preset);
+    }
+
+    // Schema 8
+    w.u16(settings.capture_threshold);
+
+    // Schema 9
+    w.u8(settings.energy_hold as u8);
+}
+
+fn decode_settings(r: &mut Reader, version: u8) -> Option<Settings> {
+    let mut settings = Settings::default();
+
+    // Schema 1
+    settings.display_brightness = r.u8()?;
+    settings.led_brightness = r.u8()?;
+    settings.default_setpoint = r.limits()?;
+    settings.protection = match r.u8()? {
+        0 => ProtectionBehaviour::FaultScreen,
+        1 => ProtectionBehaviour::ChannelOnly,
+        2 => ProtectionBehaviour::Off,
+        _ => return None,
+    };
+
+    if version >= 2 {
+        settings.budget = match r.u8()? {
+            0 => BudgetPolicy::LimitCurrent,
+            1 => BudgetPolicy::ShedA,
+            2 => BudgetPolicy::ShedB,
+            3 => BudgetPolicy::Off,
+            _ => return None,
+        };
+    }
+
+    if version >= 3 {
+        settings.ramp_rate = [r.u8()?, r.u8()?];
+    }
+
+    if version >= 4 {
+        settings.converter_mode = [r.converter_mode()?, r.converter_mode()?];
+    }
+
+    if version >= 5 {
+        settings.cable_resistance = [r.u16()?, r.u16()?];
+        settings.cable_trim = [r.u8()? != 0, r.u8()? != 0];
+    }
+
+    if version >= 6 {
+        settings.voltage_loop = r.u8()? != 0;
+    }
+
+    if version >= 7 {
+        settings.sense_preset = [sense_preset(r.u8()?)?, sense_preset(r.u8()?)?];
+    }
+
+    if version >= 8 {
+        settings.capture_threshold = r.u16()?;
+    }
+
+    if version >= 9 {
+        settings.energy_hold = r.u8()? != 0;
+    }
+
+    Some(settings)
+}
+
+fn sense_preset(value: u8) -> Option<SensePreset> {
+    match value {
+        0 => Some(SensePreset::Fast),
+        1 => Some(SensePreset::Normal),
+        2 => Some(SensePreset::LowNoise),
+        _ => None,
+    }
+}
+
+fn encode_channel(w: &mut Writer, channel: &ChannelConfig) {
+    // Schema 1
+    w.limits(&channel.target);
+    w.limits(&channel.limits);
+    for exponent in channel.precision {
+        w.u8(exponent as u8);
+    }
+}
+
+fn decode_channel(r: &mut Reader, version: u8) -> Option<ChannelConfig> {
+    // Schema 1, unchanged since
+    if version < 1 {
+        return None;
+    }
+    let target = r.limits()?;
+    let limits = r.limits()?;
+
+    let mut precision = [0i8; 4];
+    for exponent in precision.iter_mut() {
+        *exponent = r.u8()? as i8;
+    }
+
+    Some(ChannelConfig {
+        target,
+        limits,
+        precision,
+    })
+}
+
+/// Calibration records carry their own checksum on top of the storage CRC,
+/// a wrong calibration being worse than none at all
+fn encode_calibration(w: &mut Writer, calibration: &Calibration) {
+    // Schema 6, `CALIBRATION_SCHEMA`
+    let start = w.len;
+    for correction in calibration.corrections() {
+        w.bytes(&correction.gain.to_le_bytes());
+        w.bytes(&correction.offset.to_le_bytes());
+    }
+    w.u16(crc16(&w.buf[start..w.len]));
+}
+
+fn decode_calibration(r: &mut Reader, version: u8) -> Option<Calibration> {
+    // Schema 6, unchanged since. No older firmware wrote calibration records.
+    if version < CALIBRATION_SCHEMA {
+        return None;
+    }
+    let payload = r.data;
+
+    let mut corrections = [Correction::IDENTITY; 4];
+    for correction in corrections.iter_mut() {
+        correction.gain = r.f32()?;
+        correction.offset = r.f32()?;
+    }
+
+    let len = payload.len() - r.data.len();
+    if r.u16()? != crc16(&payload[..len]) {
+        warn!("config: calibration checksum mismatch");
+        return None;
+    }
+
+    let calibration = Calibration::from_corrections(corrections);
+    calibration.is_plausible().then_some(calibration)
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+struct Writer {
+    buf: [u8; MAX_VALUE_SIZE],
+    len: usize,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn limits(&mut self, limits: &Limits) {
+        self.bytes(&limits.voltage.to_le_bytes());
+        self.bytes(&limits.current.to_le_bytes());
+    }
+
+    fn converter_mode(&mut self, mode: &ConverterMode) {
+        self.u8(
+            mode.forced_pwm as u8
+                | (mode.hiccup as u8) << 1
+                | (mode.discharge as u8) << 2
+                | (mode.double_frequency as u8) << 3,
+        );
+    }
+
+    fn sense_preset(&mut self, preset: &SensePreset) {
+        self.u8(match preset {
+            SensePreset::Fast => 0,
+            SensePreset::Normal => 1,
+            SensePreset::LowNoise => 2,
+        });
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, tail) = self.data.split_first_chunk::<N>()?;
+        self.data = tail;
+        Some(*head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes::<2>().map(u16::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let value = f32::from_le_bytes(self.bytes::<4>()?);
+        value.is_finite().then_some(value)
+    }
+
+    fn limits(&mut self) -> Option<Limits> {
+        Some(Limits {
+            voltage: self.f32()?,
+            current: self.f32()?,
+        })
+    }
+
+    fn converter_mode(&mut self) -> Option<ConverterMode> {
+        let bits = self.u8()?;
+        Some(ConverterMode {
+            forced_pwm: bits & 1 != 0,
+            hiccup: bits & 1 << 1 != 0,
+            discharge: bits & 1 << 2 != 0,
+            double_frequency: bits & 1 << 3 != 0,
+        })
+    }
+}
+
+impl WithPrecision {
+    fn restore(&mut self, value: f32, exponent: i8) {
+        self.value = match self.init_range {
+            Some((min, max)) => value.clamp(min, max),
+            None => value,
+        };
+        self.precision.set_exponent(exponent);
+    }
+}
+
+impl ChannelState {
+    fn config(&self) -> ChannelConfig {
+        ChannelConfig {
+            target: self.target.get_limits(),
+            limits: self.limits.get_limits(),
+            precision: [
+                self.target.voltage.precision.get_exponent(),
+                self.target.current.precision.get_exponent(),
+                self.limits.voltage.precision.get_exponent(),
+                self.limits.current.precision.get_exponent(),
+            ],
+        }
+    }
+
+    fn restore(&mut self, config: &ChannelConfig) {
+        let [target_v, target_i, limit_v, limit_i] = config.precision;
+
+        self.target.voltage.restore(config.target.voltage, target_v);
+        self.target.current.restore(config.target.current, target_i);
+        self.limits.voltage.restore(config.limits.voltage, limit_v);
+        self.limits.current.restore(config.limits.current, limit_i);
+    }
+}
+
+impl App {
+    /// Restore persisted state, loaded once at power on before the main interface
+    pub(super) fn restore_config_task(&mut self, config: Config) -> AppTaskBuilder {
+        if let Some(mut settings) = config.settings {
+            settings.sanitize();
+            self.settings = settings;
+        }
+        if let Some(channel) = &config.ch_a {
+            self.ch_a.restore(channel);
+        }
+        if let Some(channel) = &config.ch_b {
+            self.ch_b.restore(channel);
+        }
+        for (calibration, stored) in self.calibration.iter_mut().zip(config.calibration) {
+            *calibration = stored.unwrap_or_default();
+        }
+
+        self.brightness_task().extend(self.sense_config_task())
+    }
+
+    pub(super) fn save_config_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new().hardware(HardwareTask::SaveConfig(Config {
+            settings: Some(self.settings),
+            ch_a: Some(self.ch_a.config()),
+            ch_b: Some(self.ch_b.config()),
+            calibration: [None; 2],
+        }))
+    }
+}
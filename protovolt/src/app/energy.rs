@@ -0,0 +1,83 @@
+use defmt::*;
+use embassy_time::Duration;
+
+use crate::app::{App, Screen, statistics::ReadoutView};
+use crate::hal::event::{AppTaskBuilder, Channel, DisplayTask, EnergyCount, Readout};
+
+/// Charge and energy integrated from the readouts of one channel
+#[derive(Default)]
+pub struct EnergyCounter {
+    /// As
+    charge: f64,
+    /// J
+    energy: f64,
+    elapsed: Duration,
+    /// Readout integrated up to, `None` while the output is off
+    last: Option<Readout>,
+}
+
+impl EnergyCounter {
+    /// Integrate up to a readout taken with the output on. A new output run
+    /// carries on from the last one only when the counters are held.
+    pub fn add(&mut self, readout: &Readout, hold: bool) {
+        let Some(last) = self.last.replace(*readout) else {
+            if !hold {
+                self.reset();
+            }
+            return;
+        };
+
+        let Some(dt) = readout.time.checked_duration_since(last.time) else {
+            return;
+        };
+        let seconds = dt.as_micros() as f64 / 1_000_000.0;
+
+        // Trapezoids between consecutive readouts
+        self.charge += (last.current + readout.current) as f64 / 2.0 * seconds;
+        self.energy += (last.power + readout.power) as f64 / 2.0 * seconds;
+        self.elapsed += dt;
+    }
+
+    /// The output went off, nothing is integrated until it comes back on
+    pub fn pause(&mut self) {
+        self.last = None;
+    }
+
+    /// Zero the counters, integrating on from the latest readout
+    pub fn reset(&mut self) {
+        self.charge = 0.0;
+        self.energy = 0.0;
+        self.elapsed = Duration::default();
+    }
+
+    pub fn count(&self) -> EnergyCount {
+        EnergyCount {
+            charge: (self.charge / 3.6) as f32,
+            energy: (self.energy / 3.6) as f32,
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+impl App {
+    pub(super) fn reset_energy_task(&mut self) -> AppTaskBuilder {
+        info!("energy counters reset");
+        self.ch_a.energy.reset();
+        self.ch_b.energy.reset();
+
+        self.energy_display_task(Channel::A)
+            .extend(self.energy_display_task(Channel::B))
+    }
+
+    pub(super) fn energy_display_task(&self, channel: Channel) -> AppTaskBuilder {
+        let on_main = matches!(self.interface_state.screen, Screen::Main);
+
+        match (self.interface_state.readout_view, on_main) {
+            (ReadoutView::Energy, true) => {
+                let count = self.channel_state(channel).energy.count();
+                AppTaskBuilder::new().display(DisplayTask::UpdateEnergy(channel, count))
+            }
+            _ => AppTaskBuilder::new(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use crate::hal::event::{Limits, ProtectionTrip, Readout};
+
+/// Latched OVP/OCP/SCP state of a single output channel.
+///
+/// Once tripped, the fault stays latched until acknowledged from the front
+/// panel, independently of later readouts.
+#[derive(Default)]
+pub struct Protection {
+    fault: Option<ProtectionTrip>,
+}
+
+impl Protection {
+    /// Compare the peaks of a readout against the channel's OVP/OCP limits.
+    /// Returns the trip only on the readout that latches it.
+    pub fn check(&mut self, readout: &Readout, limits: &Limits) -> Option<ProtectionTrip> {
+        if self.fault.is_some() {
+            return None;
+        }
+
+        let trip = if readout.voltage_spread.max > limits.voltage {
+            Some(ProtectionTrip::OverVoltage)
+        } else if readout.current_spread.max > limits.current {
+            Some(ProtectionTrip::OverCurrent)
+        } else {
+            None
+        };
+
+        self.fault = trip;
+        trip
+    }
+
+    /// Latch a trip raised outside the readout checks.
+    /// Returns whether the channel was not already tripped.
+    pub fn latch(&mut self, trip: ProtectionTrip) -> bool {
+        if self.fault.is_some() {
+            return false;
+        }
+
+        self.fault = Some(trip);
+        true
+    }
+
+    pub fn fault(&self) -> Option<ProtectionTrip> {
+        self.fault
+    }
+
+    /// Clear a latched fault. Returns whether there was one to clear.
+    pub fn acknowledge(&mut self) -> bool {
+        self.fault.take().is_some()
+    }
+}
@@ -0,0 +1,33 @@
+use crate::hal::event::{ConverterStatus, Limits, Readout, RegulationMode};
+
+/// Output sagging below the setpoint by more than this is no longer regulating voltage
+const CV_VOLTAGE_TOLERANCE: f32 = 0.050; // V
+const CV_VOLTAGE_TOLERANCE_RATIO: f32 = 0.02;
+
+/// One TPS55289 IOUT_LIMIT step
+const CC_CURRENT_TOLERANCE: f32 = 0.050; // A
+
+/// Derive the regulation mode of an enabled output.
+///
+/// The converter's current-limit flag is authoritative; otherwise fall back to
+/// comparing the readout against the target, which also catches a limit hit
+/// between two STATUS samples.
+pub fn regulation_mode(
+    readout: &Readout,
+    target: &Limits,
+    status: &ConverterStatus,
+) -> RegulationMode {
+    if status.current_limit {
+        return RegulationMode::ConstantCurrent;
+    }
+
+    let voltage_tolerance = CV_VOLTAGE_TOLERANCE + target.voltage * CV_VOLTAGE_TOLERANCE_RATIO;
+    let voltage_sag = target.voltage - readout.voltage > voltage_tolerance;
+    let at_current_limit = readout.current >= target.current - CC_CURRENT_TOLERANCE;
+
+    if voltage_sag && at_current_limit {
+        RegulationMode::ConstantCurrent
+    } else {
+        RegulationMode::ConstantVoltage
+    }
+}
@@ -0,0 +1,198 @@
+use defmt::*;
+use heapless::Deque;
+
+use crate::app::scpi::{Quantity, ScpiCommand, ScpiError, ScpiResponse};
+use crate::app::{App, HardwareState, Screen, WithPrecision};
+use crate::hal::event::{AppTask, AppTaskBuilder, Channel, DisplayTask, HardwareTask};
+
+const ERROR_QUEUE_SIZE: usize = 8;
+
+/// SCPI error queue, read back one entry at a time with `SYST:ERR?`
+#[derive(Default)]
+pub struct ErrorQueue {
+    errors: Deque<ScpiError, ERROR_QUEUE_SIZE>,
+}
+
+impl ErrorQueue {
+    fn push(&mut self, error: ScpiError) {
+        if self.errors.push_back(error).is_err() {
+            // The last slot reports that entries were lost
+            self.errors.pop_back();
+            let _ = self.errors.push_back(ScpiError::QueueOverflow);
+        }
+    }
+
+    fn pop(&mut self) -> Option<ScpiError> {
+        self.errors.pop_front()
+    }
+
+    fn clear(&mut self) {
+        self.errors.clear();
+    }
+}
+
+impl App {
+    pub(super) fn handle_remote_event(
+        &mut self,
+        command: Result<ScpiCommand, ScpiError>,
+    ) -> Option<AppTask> {
+        let command = match command {
+            Ok(command) => command,
+            Err(error) => {
+                self.remote_errors.push(error);
+                return None;
+            }
+        };
+
+        match self.execute_remote(command) {
+            Ok(task) => task.build(),
+            Err(error) => {
+                warn!("remote command failed: {}", error);
+                self.remote_errors.push(error);
+
+                // A query is always answered, if only with nothing
+                match command.is_query() {
+                    true => AppTaskBuilder::new()
+                        .hardware(HardwareTask::RemoteResponse(None))
+                        .build(),
+                    false => None,
+                }
+            }
+        }
+    }
+
+    fn execute_remote(&mut self, command: ScpiCommand) -> Result<AppTaskBuilder, ScpiError> {
+        // The calibration wizard owns the outputs until it is done
+        let standby = matches!(self.hardware_state, HardwareState::Standby)
+            && self.interface_state.wizard.is_none();
+
+        match command {
+            ScpiCommand::Identify => Ok(respond(ScpiResponse::Identity)),
+            ScpiCommand::QueryError => Ok(respond(ScpiResponse::Error(self.remote_errors.pop()))),
+            ScpiCommand::Reset if standby => {
+                self.remote_errors.clear();
+
+                let default = self.settings.default_setpoint;
+                for target in [&mut self.ch_a.target, &mut self.ch_b.target] {
+                    target.voltage.value = default.voltage;
+                    target.current.value = default.current;
+                }
+
+                Ok(self
+                    .disable_outputs_task()
+                    .extend(self.update_converter_task(Channel::A))
+                    .extend(self.update_converter_task(Channel::B))
+                    .extend(self.remote_display_task()))
+            }
+            ScpiCommand::SetVoltage(channel, value) if standby => {
+                let channel = Channel::from(channel);
+                set_checked(&mut self.channel_state_mut(channel).target.voltage, value)?;
+                Ok(self
+                    .update_converter_task(channel)
+                    .extend(self.remote_display_task()))
+            }
+            ScpiCommand::SetCurrent(channel, value) if standby => {
+                let channel = Channel::from(channel);
+                set_checked(&mut self.channel_state_mut(channel).target.current, value)?;
+                Ok(self
+                    .update_converter_task(channel)
+                    .extend(self.remote_display_task()))
+            }
+            ScpiCommand::QueryVoltage(channel) => {
+                let channel = Channel::from(channel);
+                let target = self.channel_state(channel).target.get_limits();
+                Ok(respond(ScpiResponse::Value(target.voltage)))
+            }
+            ScpiCommand::QueryCurrent(channel) => {
+                let channel = Channel::from(channel);
+                let target = self.channel_state(channel).target.get_limits();
+                Ok(respond(ScpiResponse::Value(target.current)))
+            }
+            ScpiCommand::SetOutput(channel, enable) if standby => {
+                let channel = Channel::from(channel);
+                let available = self.channel_available(channel);
+                let state = self.channel_state_mut(channel);
+
+                // Same rules as the channel button: no output on offline or tripped channels
+                if enable && (!available || state.protection.fault().is_some()) {
+                    return Err(ScpiError::Execution);
+                }
+
+                state.enable = enable;
+                let clear_shed = enable && core::mem::take(&mut state.shed);
+
+                let mut task = self.remote_display_task();
+                if clear_shed && matches!(self.interface_state.screen, Screen::Main) {
+                    task = task.display(DisplayTask::UpdateProtection(channel, None));
+                }
+                if available {
+                    task = task.extend(self.output_state_task(channel, enable));
+                }
+
+                Ok(task)
+            }
+            ScpiCommand::QueryOutput(channel) => {
+                let channel = Channel::from(channel);
+                Ok(respond(ScpiResponse::Bool(self.channel_state(channel).enable)))
+            }
+            ScpiCommand::ClearProtection(channel) if standby => {
+                let channel = Channel::from(channel);
+                if !self.channel_state_mut(channel).protection.acknowledge() {
+                    return Ok(AppTaskBuilder::new());
+                }
+
+                let mut task = self.remote_display_task();
+                if matches!(self.interface_state.screen, Screen::Main) {
+                    task = task.display(DisplayTask::UpdateProtection(channel, None));
+                }
+
+                Ok(task)
+            }
+            ScpiCommand::Measure(channel, quantity) => {
+                let channel = Channel::from(channel);
+                let readout = self
+                    .channel_state(channel)
+                    .readout
+                    .ok_or(ScpiError::DataStale)?;
+
+                let value = match quantity {
+                    Quantity::Voltage => readout.voltage,
+                    Quantity::Current => readout.current,
+                    Quantity::Power => readout.power,
+                    Quantity::RawVoltage => readout.raw_voltage,
+                    Quantity::RawCurrent => readout.raw_current,
+                };
+                Ok(respond(ScpiResponse::Value(value)))
+            }
+            ScpiCommand::QueryCapture => {
+                let len = self.capture_length.ok_or(ScpiError::DataStale)?;
+                Ok(respond(ScpiResponse::Capture(len)))
+            }
+            // Output control waits for the boot sequence and fault recovery
+            _ => Err(ScpiError::Execution),
+        }
+    }
+
+    /// Keep the front panel in step with remote changes
+    fn remote_display_task(&mut self) -> AppTaskBuilder {
+        match self.interface_state.screen {
+            Screen::Main => self.setpoints_task().extend(self.channel_focus_task()),
+            _ => AppTaskBuilder::new(),
+        }
+    }
+}
+
+fn respond(response: ScpiResponse) -> AppTaskBuilder {
+    AppTaskBuilder::new().hardware(HardwareTask::RemoteResponse(Some(response)))
+}
+
+fn set_checked(setter: &mut WithPrecision, value: f32) -> Result<(), ScpiError> {
+    if let Some((min, max)) = setter.init_range {
+        if !(min..=max).contains(&value) {
+            return Err(ScpiError::DataOutOfRange);
+        }
+    }
+
+    setter.value = value;
+    Ok(())
+}
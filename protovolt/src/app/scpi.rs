@@ -0,0 +1,60 @@
+//! Answers to SCPI queries, formatted for the USB serial port. The parser
+//! itself lives in the host-testable `protovolt-scpi` crate.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+pub use protovolt_scpi::{Quantity, ScpiCommand, ScpiError, parse};
+
+use crate::hal::event::Channel;
+
+impl From<protovolt_scpi::Channel> for Channel {
+    fn from(channel: protovolt_scpi::Channel) -> Self {
+        match channel {
+            protovolt_scpi::Channel::A => Channel::A,
+            protovolt_scpi::Channel::B => Channel::B,
+        }
+    }
+}
+
+const IDENTITY: &str = concat!("ThatAquarel,ProtoVolt,0,", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScpiResponse {
+    Identity,
+    Value(f32),
+    Bool(bool),
+    /// Next entry of the error queue, `None` once empty
+    Error(Option<ScpiError>),
+    /// Number of burst capture samples, each then sent on a line of its own
+    Capture(usize),
+}
+
+pub const RESPONSE_SIZE: usize = 64;
+
+impl ScpiResponse {
+    pub fn format(&self) -> String<RESPONSE_SIZE> {
+        let mut line = String::new();
+
+        let _ = match self {
+            ScpiResponse::Identity => line.push_str(IDENTITY).map_err(|_| core::fmt::Error),
+            ScpiResponse::Value(value) => write!(line, "{:.3}", value),
+            ScpiResponse::Bool(value) => write!(line, "{}", *value as u8),
+            ScpiResponse::Error(None) => write!(line, "0,\"No error\""),
+            ScpiResponse::Error(Some(e)) => write!(line, "{},\"{}\"", e.code(), e.message()),
+            ScpiResponse::Capture(len) => write!(line, "{}", len),
+        };
+
+        line
+    }
+}
+
+/// Line of the `CAPTure:DATA?` answer for one sample, `time_us,voltage,current`
+/// with the time counted from the trigger
+pub fn format_capture_sample(time: i32, voltage: f32, current: f32) -> String<RESPONSE_SIZE> {
+    let mut line = String::new();
+    let _ = write!(line, "{},{:.4},{:.4}", time, voltage, current);
+
+    line
+}
@@ -0,0 +1,534 @@
+use crate::app::{App, Screen, budget::BudgetPolicy};
+use crate::hal::converter::{CDC_STEP, CDC_STEPS_MAX};
+use crate::hal::event::{
+    AppTask, AppTaskBuilder, CaptureTrigger, Change, Channel, ConverterMode, DeviceInfo,
+    DisplayTask, HardwareTask, InterfaceEvent, Limits, SensePreset,
+};
+
+const BRIGHTNESS_STEP: u8 = 10;
+const DISPLAY_BRIGHTNESS_MIN: u8 = 10;
+
+const DEFAULT_VOLTAGE_STEP: f32 = 0.1;
+const DEFAULT_CURRENT_STEP: f32 = 0.05;
+
+const CABLE_RESISTANCE_STEP: u16 = 10;
+const CABLE_RESISTANCE_MAX: u16 = 500; // mΩ
+
+/// Selectable output ramp rates, V/s, 0 switching the ramp off
+pub const RAMP_RATES: [u8; 7] = [0, 1, 2, 5, 10, 20, 50];
+
+/// Selectable burst capture current thresholds, mA, 0 triggering on output enable
+pub const CAPTURE_THRESHOLDS: [u16; 6] = [0, 100, 500, 1000, 2000, 3000];
+
+/// What happens when a readout exceeds the channel's OVP/OCP limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProtectionBehaviour {
+    /// Disable the channel and stop on the fault screen
+    #[default]
+    FaultScreen,
+    /// Disable the channel and latch its trip tag only
+    ChannelOnly,
+    Off,
+}
+
+impl ProtectionBehaviour {
+    fn next(self) -> Self {
+        match self {
+            ProtectionBehaviour::FaultScreen => ProtectionBehaviour::ChannelOnly,
+            ProtectionBehaviour::ChannelOnly => ProtectionBehaviour::Off,
+            ProtectionBehaviour::Off => ProtectionBehaviour::FaultScreen,
+        }
+    }
+
+    fn prev(self) -> Self {
+        match self {
+            ProtectionBehaviour::FaultScreen => ProtectionBehaviour::Off,
+            ProtectionBehaviour::ChannelOnly => ProtectionBehaviour::FaultScreen,
+            ProtectionBehaviour::Off => ProtectionBehaviour::ChannelOnly,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    /// %
+    pub display_brightness: u8,
+    /// %
+    pub led_brightness: u8,
+
+    pub default_setpoint: Limits,
+    pub protection: ProtectionBehaviour,
+    pub budget: BudgetPolicy,
+
+    /// Output ramp per channel, V/s, 0 for none
+    pub ramp_rate: [u8; 2],
+    /// Converter MODE options per channel
+    pub converter_mode: [ConverterMode; 2],
+
+    /// Cable resistance to compensate per channel, mΩ
+    pub cable_resistance: [u16; 2],
+    /// Trim the cable drop the converter's CDC cannot reach from the current readout
+    pub cable_trim: [bool; 2],
+
+    /// Correct the converter voltage from the bus voltage readout
+    pub voltage_loop: bool,
+
+    /// INA226 averaging and conversion time per channel
+    pub sense_preset: [SensePreset; 2],
+
+    /// Burst capture trigger current, mA, 0 to trigger on output enable
+    pub capture_threshold: u16,
+
+    /// Carry the energy counters over when an output is switched back on
+    pub energy_hold: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            display_brightness: 100,
+            led_brightness: 100,
+            default_setpoint: Limits {
+                voltage: 5.000,
+                current: 1.000,
+            },
+            protection: Default::default(),
+            budget: Default::default(),
+            ramp_rate: [0; 2],
+            converter_mode: Default::default(),
+            cable_resistance: [0; 2],
+            cable_trim: [false; 2],
+            voltage_loop: false,
+            sense_preset: Default::default(),
+            capture_threshold: 0,
+            energy_hold: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SettingsItem {
+    DisplayBrightness,
+    LedBrightness,
+    DefaultVoltage,
+    DefaultCurrent,
+    ApplyDefaults,
+    Protection,
+    PowerBudget,
+    RampA,
+    RampB,
+    LightLoadA,
+    LightLoadB,
+    OverCurrentA,
+    OverCurrentB,
+    DischargeA,
+    DischargeB,
+    DoubleFrequencyA,
+    DoubleFrequencyB,
+    CableA,
+    CableB,
+    CableTrimA,
+    CableTrimB,
+    VoltageLoop,
+    EnergyHold,
+    SenseA,
+    SenseB,
+    CaptureTrigger,
+    CaptureA,
+    CaptureB,
+    CalibrateA,
+    CalibrateB,
+    DeviceInfo,
+}
+
+impl SettingsItem {
+    pub const ALL: [SettingsItem; 31] = [
+        SettingsItem::DisplayBrightness,
+        SettingsItem::LedBrightness,
+        SettingsItem::DefaultVoltage,
+        SettingsItem::DefaultCurrent,
+        SettingsItem::ApplyDefaults,
+        SettingsItem::Protection,
+        SettingsItem::PowerBudget,
+        SettingsItem::RampA,
+        SettingsItem::RampB,
+        SettingsItem::LightLoadA,
+        SettingsItem::LightLoadB,
+        SettingsItem::OverCurrentA,
+        SettingsItem::OverCurrentB,
+        SettingsItem::DischargeA,
+        SettingsItem::DischargeB,
+        SettingsItem::DoubleFrequencyA,
+        SettingsItem::DoubleFrequencyB,
+        SettingsItem::CableA,
+        SettingsItem::CableB,
+        SettingsItem::CableTrimA,
+        SettingsItem::CableTrimB,
+        SettingsItem::VoltageLoop,
+        SettingsItem::EnergyHold,
+        SettingsItem::SenseA,
+        SettingsItem::SenseB,
+        SettingsItem::CaptureTrigger,
+        SettingsItem::CaptureA,
+        SettingsItem::CaptureB,
+        SettingsItem::CalibrateA,
+        SettingsItem::CalibrateB,
+        SettingsItem::DeviceInfo,
+    ];
+
+    /// Channel whose converter MODE the item configures
+    pub fn mode_channel(self) -> Option<Channel> {
+        match self {
+            SettingsItem::LightLoadA
+            | SettingsItem::OverCurrentA
+            | SettingsItem::DischargeA
+            | SettingsItem::DoubleFrequencyA => Some(Channel::A),
+            SettingsItem::LightLoadB
+            | SettingsItem::OverCurrentB
+            | SettingsItem::DischargeB
+            | SettingsItem::DoubleFrequencyB => Some(Channel::B),
+            _ => None,
+        }
+    }
+
+    /// Channel whose cable compensation the item configures
+    pub fn cable_channel(self) -> Option<Channel> {
+        match self {
+            SettingsItem::CableA | SettingsItem::CableTrimA => Some(Channel::A),
+            SettingsItem::CableB | SettingsItem::CableTrimB => Some(Channel::B),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum SettingsPage {
+    #[default]
+    Menu,
+    DeviceInfo,
+    Calibration,
+    Capture(Channel),
+}
+
+impl Settings {
+    /// Bring values restored from flash back into their adjustable ranges
+    pub fn sanitize(&mut self) {
+        self.display_brightness = self.display_brightness.clamp(DISPLAY_BRIGHTNESS_MIN, 100);
+        self.led_brightness = self.led_brightness.min(100);
+        self.default_setpoint.voltage = self.default_setpoint.voltage.clamp(0.2, 20.0);
+        self.default_setpoint.current = self.default_setpoint.current.clamp(0.0, 5.0);
+
+        for rate in self.ramp_rate.iter_mut() {
+            if !RAMP_RATES.contains(rate) {
+                *rate = 0;
+            }
+        }
+        for resistance in self.cable_resistance.iter_mut() {
+            *resistance = (*resistance).min(CABLE_RESISTANCE_MAX);
+        }
+        if !CAPTURE_THRESHOLDS.contains(&self.capture_threshold) {
+            self.capture_threshold = 0;
+        }
+    }
+
+    /// Part of a channel's cable resistance the converter CDC compensates, mΩ
+    pub fn hardware_compensation(&self, channel: Channel) -> u16 {
+        let steps = (self.cable_resistance[channel as usize] / CDC_STEP).min(CDC_STEPS_MAX);
+        steps * CDC_STEP
+    }
+
+    /// Cable resistance left over for the software trim in Ω, if it is on
+    pub fn software_compensation(&self, channel: Channel) -> Option<f32> {
+        if !self.cable_trim[channel as usize] {
+            return None;
+        }
+
+        let rest = self.cable_resistance[channel as usize] - self.hardware_compensation(channel);
+        (rest > 0).then(|| rest as f32 / 1000.0)
+    }
+
+    /// Ramp rate of a channel in V/s, if it ramps at all
+    pub fn ramp(&self, channel: Channel) -> Option<f32> {
+        match self.ramp_rate[channel as usize] {
+            0 => None,
+            rate => Some(rate as f32),
+        }
+    }
+
+    pub fn capture_trigger(&self) -> CaptureTrigger {
+        match self.capture_threshold {
+            0 => CaptureTrigger::OutputEnable,
+            threshold => CaptureTrigger::Current(threshold as f32 / 1000.0),
+        }
+    }
+
+    fn adjust(&mut self, item: SettingsItem, increase: bool) {
+        let sign = if increase { 1.0 } else { -1.0 };
+
+        match item {
+            SettingsItem::DisplayBrightness => {
+                self.display_brightness =
+                    step_percent(self.display_brightness, increase).max(DISPLAY_BRIGHTNESS_MIN)
+            }
+            SettingsItem::LedBrightness => {
+                self.led_brightness = step_percent(self.led_brightness, increase)
+            }
+            SettingsItem::DefaultVoltage => {
+                let next = self.default_setpoint.voltage + sign * DEFAULT_VOLTAGE_STEP;
+                self.default_setpoint.voltage = next.clamp(0.2, 20.0);
+            }
+            SettingsItem::DefaultCurrent => {
+                let next = self.default_setpoint.current + sign * DEFAULT_CURRENT_STEP;
+                self.default_setpoint.current = next.clamp(0.0, 5.0);
+            }
+            SettingsItem::Protection => {
+                self.protection = match increase {
+                    true => self.protection.next(),
+                    false => self.protection.prev(),
+                }
+            }
+            SettingsItem::PowerBudget => {
+                self.budget = match increase {
+                    true => self.budget.next(),
+                    false => self.budget.prev(),
+                }
+            }
+            SettingsItem::RampA => step_ramp(&mut self.ramp_rate[0], increase),
+            SettingsItem::RampB => step_ramp(&mut self.ramp_rate[1], increase),
+            SettingsItem::CableA | SettingsItem::CableB => {
+                if let Some(channel) = item.cable_channel() {
+                    let resistance = &mut self.cable_resistance[channel as usize];
+                    *resistance = match increase {
+                        true => (*resistance + CABLE_RESISTANCE_STEP).min(CABLE_RESISTANCE_MAX),
+                        false => resistance.saturating_sub(CABLE_RESISTANCE_STEP),
+                    };
+                }
+            }
+            SettingsItem::CableTrimA | SettingsItem::CableTrimB => {
+                if let Some(channel) = item.cable_channel() {
+                    let trim = &mut self.cable_trim[channel as usize];
+                    *trim = !*trim;
+                }
+            }
+            SettingsItem::VoltageLoop => self.voltage_loop = !self.voltage_loop,
+            SettingsItem::EnergyHold => self.energy_hold = !self.energy_hold,
+            SettingsItem::SenseA => step_sense(&mut self.sense_preset[0], increase),
+            SettingsItem::SenseB => step_sense(&mut self.sense_preset[1], increase),
+            SettingsItem::CaptureTrigger => {
+                step_capture_threshold(&mut self.capture_threshold, increase)
+            }
+            SettingsItem::LightLoadA | SettingsItem::LightLoadB => {
+                toggle_mode(&mut self.converter_mode, item, |mode| &mut mode.forced_pwm)
+            }
+            SettingsItem::OverCurrentA | SettingsItem::OverCurrentB => {
+                toggle_mode(&mut self.converter_mode, item, |mode| &mut mode.hiccup)
+            }
+            SettingsItem::DischargeA | SettingsItem::DischargeB => {
+                toggle_mode(&mut self.converter_mode, item, |mode| &mut mode.discharge)
+            }
+            SettingsItem::DoubleFrequencyA | SettingsItem::DoubleFrequencyB => {
+                toggle_mode(&mut self.converter_mode, item, |mode| &mut mode.double_frequency)
+            }
+            SettingsItem::ApplyDefaults
+            | SettingsItem::CaptureA
+            | SettingsItem::CaptureB
+            | SettingsItem::CalibrateA
+            | SettingsItem::CalibrateB
+            | SettingsItem::DeviceInfo => {}
+        }
+    }
+}
+
+fn step_percent(value: u8, increase: bool) -> u8 {
+    match increase {
+        true => value.saturating_add(BRIGHTNESS_STEP).min(100),
+        false => value.saturating_sub(BRIGHTNESS_STEP),
+    }
+}
+
+fn step_ramp(rate: &mut u8, increase: bool) {
+    let index = RAMP_RATES.iter().position(|r| r == rate).unwrap_or(0);
+    let index = match increase {
+        true => (index + 1).min(RAMP_RATES.len() - 1),
+        false => index.saturating_sub(1),
+    };
+
+    *rate = RAMP_RATES[index];
+}
+
+fn step_capture_threshold(threshold: &mut u16, increase: bool) {
+    let index = CAPTURE_THRESHOLDS.iter().position(|t| t == threshold).unwrap_or(0);
+    let index = match increase {
+        true => (index + 1).min(CAPTURE_THRESHOLDS.len() - 1),
+        false => index.saturating_sub(1),
+    };
+
+    *threshold = CAPTURE_THRESHOLDS[index];
+}
+
+fn step_sense(preset: &mut SensePreset, increase: bool) {
+    *preset = match (*preset, increase) {
+        (SensePreset::Fast, true) | (SensePreset::LowNoise, false) => SensePreset::Normal,
+        (SensePreset::Normal, true) | (SensePreset::LowNoise, true) => SensePreset::LowNoise,
+        (SensePreset::Normal, false) | (SensePreset::Fast, false) => SensePreset::Fast,
+    };
+}
+
+fn toggle_mode(
+    modes: &mut [ConverterMode; 2],
+    item: SettingsItem,
+    option: fn(&mut ConverterMode) -> &mut bool,
+) {
+    if let Some(channel) = item.mode_channel() {
+        let option = option(&mut modes[channel as usize]);
+        *option = !*option;
+    }
+}
+
+impl App {
+    pub(super) fn handle_settings_interface_event(
+        &mut self,
+        event: InterfaceEvent,
+    ) -> Option<AppTask> {
+        if let SettingsPage::Calibration = self.interface_state.settings_page {
+            return self.handle_calibration_event(event);
+        }
+        if let SettingsPage::Capture(channel) = self.interface_state.settings_page {
+            return self.handle_capture_event(channel, event);
+        }
+        if let SettingsPage::DeviceInfo = self.interface_state.settings_page {
+            return match event {
+                InterfaceEvent::ButtonEnter(Change::Pressed) => {
+                    self.interface_state.settings_page = SettingsPage::Menu;
+                    self.setup_settings_task().build()
+                }
+                InterfaceEvent::ButtonSettings(Change::Pressed) => self.exit_settings_task(),
+                _ => None,
+            };
+        }
+
+        let cursor = &mut self.interface_state.settings_cursor;
+        let item = SettingsItem::ALL[*cursor];
+
+        match event {
+            InterfaceEvent::ButtonUp => {
+                *cursor = cursor.saturating_sub(1);
+                self.update_settings_task().build()
+            }
+            InterfaceEvent::ButtonDown => {
+                *cursor = (*cursor + 1).min(SettingsItem::ALL.len() - 1);
+                self.update_settings_task().build()
+            }
+            InterfaceEvent::ButtonLeft => self.adjust_setting_task(item, false),
+            InterfaceEvent::ButtonRight => self.adjust_setting_task(item, true),
+            InterfaceEvent::ButtonEnter(Change::Pressed) => match item {
+                SettingsItem::ApplyDefaults => self.apply_default_setpoints_task().build(),
+                SettingsItem::CaptureA => self.start_capture_task(Channel::A),
+                SettingsItem::CaptureB => self.start_capture_task(Channel::B),
+                SettingsItem::CalibrateA => self.start_calibration_task(Channel::A),
+                SettingsItem::CalibrateB => self.start_calibration_task(Channel::B),
+                SettingsItem::DeviceInfo => {
+                    self.interface_state.settings_page = SettingsPage::DeviceInfo;
+                    AppTaskBuilder::display_task(DisplayTask::SetupDeviceInfo(
+                        self.device_info(),
+                    ))
+                }
+                _ => None,
+            },
+            InterfaceEvent::ButtonSettings(Change::Pressed) => self.exit_settings_task(),
+            _ => None,
+        }
+    }
+
+    pub(super) fn enter_settings_task(&mut self) -> AppTaskBuilder {
+        self.interface_state.screen = Screen::Settings;
+        self.interface_state.settings_page = SettingsPage::Menu;
+        self.discard_setpoint_edit();
+
+        self.setup_settings_task()
+    }
+
+    fn exit_settings_task(&mut self) -> Option<AppTask> {
+        self.save_config_task().extend(self.setup_main_task()).build()
+    }
+
+    pub(super) fn setup_settings_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new()
+            .display(DisplayTask::SetupSettings)
+            .extend(self.update_settings_task())
+    }
+
+    fn update_settings_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new().display(DisplayTask::UpdateSettings(
+            self.settings,
+            self.interface_state.settings_cursor,
+        ))
+    }
+
+    pub(super) fn brightness_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new().display(DisplayTask::UpdateBrightness(
+            self.settings.display_brightness,
+            self.settings.led_brightness,
+        ))
+    }
+
+    fn adjust_setting_task(&mut self, item: SettingsItem, increase: bool) -> Option<AppTask> {
+        self.settings.adjust(item, increase);
+
+        let task = self.update_settings_task();
+        match item {
+            SettingsItem::DisplayBrightness | SettingsItem::LedBrightness => {
+                task.extend(self.brightness_task()).build()
+            }
+            SettingsItem::Protection => task.extend(self.sense_alert_limits_task()).build(),
+            SettingsItem::SenseA | SettingsItem::SenseB => {
+                task.extend(self.sense_config_task()).build()
+            }
+            SettingsItem::CableA | SettingsItem::CableB => match item.cable_channel() {
+                Some(channel) => task.extend(self.cable_compensation_task(channel)).build(),
+                None => task.build(),
+            },
+            _ => match item.mode_channel() {
+                Some(channel) => task.extend(self.converter_mode_task(channel)).build(),
+                None => task.build(),
+            },
+        }
+    }
+
+    /// Apply a channel's MODE options to its converter
+    pub(super) fn converter_mode_task(&self, channel: Channel) -> AppTaskBuilder {
+        if !self.converter_ok(channel) {
+            return AppTaskBuilder::new();
+        }
+
+        let mode = self.settings.converter_mode[channel as usize];
+        AppTaskBuilder::new().hardware(HardwareTask::UpdateConverterMode(channel, mode))
+    }
+
+    /// Apply both channels' averaging presets to the INA226s
+    pub(super) fn sense_config_task(&self) -> AppTaskBuilder {
+        AppTaskBuilder::new().hardware(HardwareTask::UpdateSenseConfig(self.settings.sense_preset))
+    }
+
+    /// Reset both channel targets to the default setpoints
+    fn apply_default_setpoints_task(&mut self) -> AppTaskBuilder {
+        let default = self.settings.default_setpoint;
+
+        for target in [&mut self.ch_a.target, &mut self.ch_b.target] {
+            target.voltage.value = default.voltage;
+            target.current.value = default.current;
+        }
+
+        self.update_converter_task(Channel::A)
+            .extend(self.update_converter_task(Channel::B))
+    }
+
+    fn device_info(&self) -> DeviceInfo {
+        DeviceInfo {
+            power_type: self.power_type,
+            power_delivery: self.power_delivery_test,
+            sense: self.sense_test,
+            converter: self.converter_test,
+        }
+    }
+}
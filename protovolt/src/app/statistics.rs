@@ -0,0 +1,174 @@
+use defmt::*;
+use heapless::Deque;
+
+use crate::app::{App, Screen};
+use crate::hal::event::{
+    AppTaskBuilder, Channel, DisplayTask, Readout, Spread, Statistics, StatisticsWindow,
+};
+
+/// Readouts kept for the rolling statistics
+const WINDOW_LENGTH: usize = 64;
+
+/// What the readout area of the main screen shows
+#[derive(Clone, Copy, Default, Format)]
+pub enum ReadoutView {
+    #[default]
+    Live,
+    Statistics(StatisticsWindow),
+    Energy,
+}
+
+#[derive(Clone, Copy)]
+struct Accumulator {
+    min: f32,
+    max: f32,
+    /// Long runs lose the small readouts in an f32 sum
+    sum: f64,
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Accumulator {
+    /// Add `samples` values spread as given
+    fn add(&mut self, spread: &Spread, samples: u32) {
+        self.min = self.min.min(spread.min);
+        self.max = self.max.max(spread.max);
+        self.sum += spread.mean as f64 * samples as f64;
+    }
+
+    fn spread(&self, count: u32) -> Spread {
+        match count {
+            0 => Spread::default(),
+            _ => Spread {
+                min: self.min,
+                max: self.max,
+                mean: (self.sum / count as f64) as f32,
+            },
+        }
+    }
+}
+
+/// Voltage, current and power statistics of one channel, over every
+/// conversion combined into its readouts
+#[derive(Default)]
+pub struct ChannelStatistics {
+    window: Deque<([Spread; 3], u32), WINDOW_LENGTH>,
+    total: [Accumulator; 3],
+    count: u32,
+}
+
+impl ChannelStatistics {
+    pub fn add(&mut self, readout: &Readout) {
+        let spreads = [readout.voltage_spread, readout.current_spread, readout.power_spread];
+
+        if self.window.is_full() {
+            self.window.pop_front();
+        }
+        let _ = self.window.push_back((spreads, readout.samples));
+
+        for (total, spread) in self.total.iter_mut().zip(&spreads) {
+            total.add(spread, readout.samples);
+        }
+        self.count = self.count.saturating_add(readout.samples);
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn summary(&self, window: StatisticsWindow) -> Statistics {
+        let (accumulators, count) = match window {
+            StatisticsWindow::Rolling => {
+                let (mut rolling, mut count) = ([Accumulator::default(); 3], 0);
+                for (spreads, samples) in self.window.iter() {
+                    for (accumulator, spread) in rolling.iter_mut().zip(spreads) {
+                        accumulator.add(spread, *samples);
+                    }
+                    count += samples;
+                }
+                (rolling, count)
+            }
+            StatisticsWindow::SinceReset => (self.total, self.count),
+        };
+
+        Statistics {
+            window,
+            voltage: accumulators[0].spread(count),
+            current: accumulators[1].spread(count),
+            power: accumulators[2].spread(count),
+            count,
+        }
+    }
+}
+
+impl App {
+    /// Step the main screen through live readouts, rolling and since-reset
+    /// statistics, then the energy counters
+    pub(super) fn cycle_readout_view_task(&mut self) -> AppTaskBuilder {
+        let view = &mut self.interface_state.readout_view;
+        *view = match view {
+            ReadoutView::Live => ReadoutView::Statistics(StatisticsWindow::Rolling),
+            ReadoutView::Statistics(StatisticsWindow::Rolling) => {
+                ReadoutView::Statistics(StatisticsWindow::SinceReset)
+            }
+            ReadoutView::Statistics(StatisticsWindow::SinceReset) => ReadoutView::Energy,
+            ReadoutView::Energy => ReadoutView::Live,
+        };
+        info!("readout view {}", *view);
+
+        // Clear what the previous view left, the next readouts fill it back in
+        self.setup_main_task()
+    }
+
+    /// Zero whatever the readout view shows
+    pub(super) fn reset_readout_view_task(&mut self) -> AppTaskBuilder {
+        match self.interface_state.readout_view {
+            ReadoutView::Energy => self.reset_energy_task(),
+            _ => self.reset_statistics_task(),
+        }
+    }
+
+    fn reset_statistics_task(&mut self) -> AppTaskBuilder {
+        info!("statistics reset");
+        self.ch_a.statistics.reset();
+        self.ch_b.statistics.reset();
+
+        self.statistics_display_task(Channel::A)
+            .extend(self.statistics_display_task(Channel::B))
+    }
+
+    /// Latest readout of a channel, or what the readout view makes of it
+    pub(super) fn readout_display_task(
+        &self,
+        channel: Channel,
+        readout: Readout,
+    ) -> AppTaskBuilder {
+        match self.interface_state.readout_view {
+            ReadoutView::Live => {
+                AppTaskBuilder::new().display(DisplayTask::UpdateReadout(channel, readout))
+            }
+            ReadoutView::Statistics(_) => self.statistics_display_task(channel),
+            ReadoutView::Energy => self.energy_display_task(channel),
+        }
+    }
+
+    fn statistics_display_task(&self, channel: Channel) -> AppTaskBuilder {
+        let on_main = matches!(self.interface_state.screen, Screen::Main);
+
+        match (self.interface_state.readout_view, on_main) {
+            (ReadoutView::Statistics(window), true) => {
+                let statistics = self.channel_state(channel).statistics.summary(window);
+                AppTaskBuilder::new().display(DisplayTask::UpdateStatistics(channel, statistics))
+            }
+            _ => AppTaskBuilder::new(),
+        }
+    }
+}
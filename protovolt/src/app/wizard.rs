@@ -0,0 +1,280 @@
+use defmt::*;
+
+use crate::app::{App, DecimalPrecision, WithPrecision, settings::SettingsPage};
+use crate::hal::calibration::{Calibration, Correction};
+use crate::hal::event::{
+    AppTask, AppTaskBuilder, CalibrationQuantity, CalibrationStep, Change, Channel, DisplayTask,
+    HardwareTask, InterfaceEvent,
+};
+
+/// Output voltages the voltage calibration is taken at, into a voltmeter
+const VOLTAGE_POINTS: [f32; 2] = [2.0, 15.0]; // V
+/// Current limits the current calibration is taken at, into a shorting ammeter
+const CURRENT_POINTS: [f32; 2] = [0.5, 2.5]; // A
+
+/// Current limit while the output drives a voltmeter
+const VOLTAGE_STEP_CURRENT: f32 = 0.1; // A
+/// Output voltage while the current limit drives an ammeter
+const CURRENT_STEP_VOLTAGE: f32 = 1.0; // V
+
+const STEP_COUNT: usize = VOLTAGE_POINTS.len() + CURRENT_POINTS.len();
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    /// Nominal value programmed into the converter
+    programmed: f32,
+    /// Uncalibrated INA226 reading
+    raw: f32,
+    /// Read off the external DMM
+    measured: f32,
+}
+
+/// Calibration wizard in progress on one channel
+pub struct Wizard {
+    channel: Channel,
+    step: usize,
+    entry: WithPrecision,
+    samples: [Sample; STEP_COUNT],
+    /// The previous attempt fitted an implausible calibration
+    rejected: bool,
+}
+
+impl Wizard {
+    fn new(channel: Channel) -> Self {
+        let mut wizard = Self {
+            channel,
+            step: 0,
+            entry: Default::default(),
+            samples: [Sample::default(); STEP_COUNT],
+            rejected: false,
+        };
+        wizard.reset_entry();
+
+        wizard
+    }
+
+    fn point(&self) -> (CalibrationQuantity, f32) {
+        match self.step.checked_sub(VOLTAGE_POINTS.len()) {
+            None => (CalibrationQuantity::Voltage, VOLTAGE_POINTS[self.step]),
+            Some(i) => (CalibrationQuantity::Current, CURRENT_POINTS[i]),
+        }
+    }
+
+    /// Start the DMM entry off at the nominal value of the step
+    fn reset_entry(&mut self) {
+        let (quantity, point) = self.point();
+        let range = match quantity {
+            CalibrationQuantity::Voltage => (0.0, 25.0),
+            CalibrationQuantity::Current => (0.0, 6.0),
+        };
+
+        self.entry = WithPrecision {
+            value: point,
+            precision: DecimalPrecision { exponent: -2 },
+            init_range: Some(range),
+            min_step: 0.01,
+        };
+    }
+
+    fn view(&self) -> CalibrationStep {
+        let (quantity, point) = self.point();
+
+        CalibrationStep {
+            channel: self.channel,
+            index: self.step,
+            count: STEP_COUNT,
+            quantity,
+            point,
+            measured: self.entry.value(),
+            precision: self.entry.precision,
+            rejected: self.rejected,
+        }
+    }
+
+    /// Fit a calibration to the samples, if it is one a real board could have
+    fn calibration(&self) -> Option<Calibration> {
+        let (voltage, current) = self.samples.split_at(VOLTAGE_POINTS.len());
+
+        let calibration = Calibration {
+            set_voltage: fit(voltage, |s| (s.measured, s.programmed))?,
+            set_current: fit(current, |s| (s.measured, s.programmed))?,
+            readout_voltage: fit(voltage, |s| (s.raw, s.measured))?,
+            readout_current: fit(current, |s| (s.raw, s.measured))?,
+        };
+
+        calibration.is_plausible().then_some(calibration)
+    }
+}
+
+/// Least-squares line through the samples, as the correction taking x onto y
+fn fit(samples: &[Sample], point: fn(&Sample) -> (f32, f32)) -> Option<Correction> {
+    let n = samples.len() as f32;
+    let (sum_x, sum_y) = samples
+        .iter()
+        .map(point)
+        .fold((0.0, 0.0), |(sum_x, sum_y), (x, y)| (sum_x + x, sum_y + y));
+    let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+
+    let (mut sxx, mut sxy) = (0.0, 0.0);
+    for (x, y) in samples.iter().map(point) {
+        sxx += (x - mean_x) * (x - mean_x);
+        sxy += (x - mean_x) * (y - mean_y);
+    }
+
+    // Points on top of each other do not give a slope
+    if sxx < 1e-6 {
+        return None;
+    }
+
+    let gain = sxy / sxx;
+    Some(Correction {
+        gain,
+        offset: mean_y - gain * mean_x,
+    })
+}
+
+impl App {
+    pub(super) fn handle_calibration_event(&mut self, event: InterfaceEvent) -> Option<AppTask> {
+        let wizard = self.interface_state.wizard.as_mut()?;
+
+        match event {
+            InterfaceEvent::ButtonUp => wizard.entry.increment(),
+            InterfaceEvent::ButtonDown => wizard.entry.decrement(),
+            InterfaceEvent::ButtonLeft => wizard.entry.cursor_left(),
+            InterfaceEvent::ButtonRight => wizard.entry.cursor_right(),
+            InterfaceEvent::ButtonEnter(Change::Pressed) => return self.record_calibration_task(),
+            InterfaceEvent::ButtonSettings(Change::Pressed) => {
+                return self
+                    .cancel_calibration_task()
+                    .extend(self.setup_settings_task())
+                    .build();
+            }
+            _ => return None,
+        }
+
+        AppTaskBuilder::display_task(DisplayTask::UpdateCalibration(wizard.view()))
+    }
+
+    /// Take a channel over for calibration, with its outputs programmed raw
+    pub(super) fn start_calibration_task(&mut self, channel: Channel) -> Option<AppTask> {
+        if !self.channel_available(channel) {
+            return None;
+        }
+
+        let state = self.channel_state_mut(channel);
+        state.enable = false;
+        state.cable_trim = 0.0;
+        state.loop_trim = 0.0;
+
+        self.interface_state.settings_page = SettingsPage::Calibration;
+        self.interface_state.wizard = Some(Wizard::new(channel));
+
+        AppTaskBuilder::new()
+            .hardware(HardwareTask::UpdateConverterState(channel, false))
+            .hardware(HardwareTask::ApplyCalibration(channel, Calibration::IDENTITY))
+            .display(DisplayTask::SetupCalibration(channel))
+            .extend(self.calibration_step_task())
+            .extend(self.sense_alert_limits_task())
+            .build()
+    }
+
+    /// Drive the output to the point of the current step
+    fn calibration_step_task(&self) -> AppTaskBuilder {
+        let Some(wizard) = &self.interface_state.wizard else {
+            return AppTaskBuilder::new();
+        };
+
+        let (voltage, current) = match wizard.point() {
+            (CalibrationQuantity::Voltage, voltage) => (voltage, VOLTAGE_STEP_CURRENT),
+            (CalibrationQuantity::Current, current) => (CURRENT_STEP_VOLTAGE, current),
+        };
+
+        // Voltage first, so that the output never sees a high voltage with a high limit
+        AppTaskBuilder::new()
+            .hardware(HardwareTask::UpdateConverterVoltage(wizard.channel, voltage))
+            .hardware(HardwareTask::UpdateConverterCurrent(wizard.channel, current))
+            .hardware(HardwareTask::UpdateConverterState(wizard.channel, true))
+            .display(DisplayTask::UpdateCalibration(wizard.view()))
+    }
+
+    /// Pair the DMM entry with the raw readout, then move on to the next
+    /// step or fit and save the calibration
+    fn record_calibration_task(&mut self) -> Option<AppTask> {
+        let channel = self.interface_state.wizard.as_ref()?.channel;
+        let readout = self.channel_state(channel).readout?;
+        let wizard = self.interface_state.wizard.as_mut()?;
+
+        let (quantity, point) = wizard.point();
+        wizard.samples[wizard.step] = Sample {
+            programmed: point,
+            raw: match quantity {
+                CalibrationQuantity::Voltage => readout.raw_voltage,
+                CalibrationQuantity::Current => readout.raw_current,
+            },
+            measured: wizard.entry.value(),
+        };
+
+        wizard.step += 1;
+        if wizard.step < STEP_COUNT {
+            wizard.reset_entry();
+            return self.calibration_step_task().build();
+        }
+
+        let Some(calibration) = wizard.calibration() else {
+            warn!("calibration {}: fit rejected, restarting", channel);
+            *wizard = Wizard::new(channel);
+            wizard.rejected = true;
+            return self.calibration_step_task().build();
+        };
+
+        info!("calibration {}: {}", channel, Debug2Format(&calibration));
+        self.calibration[channel as usize] = calibration;
+        self.interface_state.wizard = None;
+        self.interface_state.settings_page = SettingsPage::Menu;
+
+        AppTaskBuilder::new()
+            .hardware(HardwareTask::UpdateConverterState(channel, false))
+            .hardware(HardwareTask::SaveCalibration(channel, calibration))
+            .extend(self.update_converter_task(channel))
+            .extend(self.sense_alert_limits_task())
+            .extend(self.setup_settings_task())
+            .build()
+    }
+
+    /// Leave the wizard, putting back the calibration the channel had
+    pub(super) fn cancel_calibration_task(&mut self) -> AppTaskBuilder {
+        let Some(wizard) = self.interface_state.wizard.take() else {
+            return AppTaskBuilder::new();
+        };
+        self.interface_state.settings_page = SettingsPage::Menu;
+
+        let channel = wizard.channel;
+        AppTaskBuilder::new()
+            .hardware(HardwareTask::UpdateConverterState(channel, false))
+            .hardware(HardwareTask::ApplyCalibration(
+                channel,
+                self.calibration[channel as usize],
+            ))
+            .extend(self.update_converter_task(channel))
+            .extend(self.sense_alert_limits_task())
+    }
+
+    /// Leave the wizard on a fault of the channel it drives, back to the settings menu
+    pub(super) fn abort_calibration_task(&mut self, channel: Channel) -> AppTaskBuilder {
+        if !self.calibrating(channel) {
+            return AppTaskBuilder::new();
+        }
+
+        warn!("calibration {}: aborted on a fault", channel);
+        self.cancel_calibration_task()
+            .extend(self.setup_settings_task())
+    }
+
+    /// Channel the wizard drives directly, bypassing the usual setpoint path
+    pub(super) fn calibrating(&self, channel: Channel) -> bool {
+        self.interface_state
+            .wizard
+            .as_ref()
+            .is_some_and(|wizard| wizard.channel == channel)
+    }
+}
@@ -0,0 +1,103 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+
+use crate::hal::event::Channel;
+
+/// Calibrations outside this are taken for corrupt rather than for a real board
+const GAIN_RANGE: (f32, f32) = (0.8, 1.25);
+const OFFSET_MAX: f32 = 0.5; // V or A
+
+/// Linear correction, `gain * value + offset`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Correction {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Correction {
+    pub const IDENTITY: Self = Self {
+        gain: 1.0,
+        offset: 0.0,
+    };
+
+    pub fn apply(&self, value: f32) -> f32 {
+        self.gain * value + self.offset
+    }
+
+    /// Value that `apply` takes onto `value`
+    pub fn invert(&self, value: f32) -> f32 {
+        (value - self.offset) / self.gain
+    }
+
+    fn is_plausible(&self) -> bool {
+        (GAIN_RANGE.0..=GAIN_RANGE.1).contains(&self.gain) && self.offset.abs() <= OFFSET_MAX
+    }
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Calibration of one channel.
+///
+/// Setpoint corrections map the wanted output onto the nominal value to
+/// program into the converter; readout corrections map the raw INA226
+/// reading onto the true value.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Calibration {
+    pub set_voltage: Correction,
+    pub set_current: Correction,
+    pub readout_voltage: Correction,
+    pub readout_current: Correction,
+}
+
+impl Calibration {
+    pub const IDENTITY: Self = Self {
+        set_voltage: Correction::IDENTITY,
+        set_current: Correction::IDENTITY,
+        readout_voltage: Correction::IDENTITY,
+        readout_current: Correction::IDENTITY,
+    };
+
+    pub fn corrections(&self) -> [Correction; 4] {
+        [
+            self.set_voltage,
+            self.set_current,
+            self.readout_voltage,
+            self.readout_current,
+        ]
+    }
+
+    pub fn from_corrections(corrections: [Correction; 4]) -> Self {
+        let [set_voltage, set_current, readout_voltage, readout_current] = corrections;
+        Self {
+            set_voltage,
+            set_current,
+            readout_voltage,
+            readout_current,
+        }
+    }
+
+    pub fn is_plausible(&self) -> bool {
+        self.corrections().iter().all(Correction::is_plausible)
+    }
+}
+
+/// Calibration in effect, shared between the converter path and the sense task
+static CALIBRATION: Mutex<ThreadModeRawMutex, Cell<[Calibration; 2]>> =
+    Mutex::new(Cell::new([Calibration::IDENTITY; 2]));
+
+pub fn get(channel: Channel) -> Calibration {
+    CALIBRATION.lock(|calibration| calibration.get()[channel as usize])
+}
+
+pub fn set(channel: Channel, value: Calibration) {
+    CALIBRATION.lock(|calibration| {
+        let mut all = calibration.get();
+        all[channel as usize] = value;
+        calibration.set(all);
+    });
+}
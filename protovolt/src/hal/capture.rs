@@ -0,0 +1,136 @@
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::ThreadModeRawMutex},
+    signal::Signal,
+};
+use embassy_time::Instant;
+
+/// Samples kept around the trigger
+pub const CAPTURE_LENGTH: usize = 1024;
+/// Samples kept from before the trigger, once the capture ran that long
+pub const PRE_TRIGGER: usize = 128;
+
+/// Capture sample, timed against the trigger
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sample {
+    /// µs, negative before the trigger
+    pub time: i32,
+    pub voltage: f32,
+    pub current: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    /// µs since boot, wrapping
+    time: u32,
+    voltage: f32,
+    current: f32,
+}
+
+/// Ring buffer of calibrated readings of one channel
+pub struct Capture {
+    records: [Record; CAPTURE_LENGTH],
+    /// Slot the next record goes into
+    head: usize,
+    len: usize,
+    trigger_time: u32,
+    /// Records still to take after the trigger, `None` until it fired
+    remaining: Option<usize>,
+}
+
+impl Capture {
+    const fn new() -> Self {
+        Self {
+            records: [Record {
+                time: 0,
+                voltage: 0.0,
+                current: 0.0,
+            }; CAPTURE_LENGTH],
+            head: 0,
+            len: 0,
+            trigger_time: 0,
+            remaining: None,
+        }
+    }
+
+    pub(super) fn start(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.remaining = None;
+    }
+
+    pub(super) fn push(&mut self, time: Instant, voltage: f32, current: f32) {
+        self.records[self.head] = Record {
+            time: time.as_micros() as u32,
+            voltage,
+            current,
+        };
+        self.head = (self.head + 1) % CAPTURE_LENGTH;
+        self.len = (self.len + 1).min(CAPTURE_LENGTH);
+
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(1);
+        }
+    }
+
+    /// Take the latest record as the trigger
+    pub(super) fn trigger(&mut self) {
+        let latest = (self.head + CAPTURE_LENGTH - 1) % CAPTURE_LENGTH;
+        self.trigger_time = self.records[latest].time;
+
+        // The ring then drops all but the last `PRE_TRIGGER` records before it
+        self.remaining = Some(CAPTURE_LENGTH - PRE_TRIGGER - 1);
+    }
+
+    pub(super) fn is_triggered(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sample `index` counted from the oldest one kept
+    pub fn get(&self, index: usize) -> Option<Sample> {
+        if index >= self.len {
+            return None;
+        }
+
+        let slot = (self.head + CAPTURE_LENGTH - self.len + index) % CAPTURE_LENGTH;
+        let record = self.records[slot];
+        Some(Sample {
+            time: record.time.wrapping_sub(self.trigger_time) as i32,
+            voltage: record.voltage,
+            current: record.current,
+        })
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        (0..self.len).filter_map(|index| self.get(index))
+    }
+}
+
+/// Last capture, filled in by the sense task and read back by the display and USB
+static CAPTURE: Mutex<ThreadModeRawMutex, RefCell<Capture>> =
+    Mutex::new(RefCell::new(Capture::new()));
+
+pub fn with<R>(f: impl FnOnce(&Capture) -> R) -> R {
+    CAPTURE.lock(|capture| f(&capture.borrow()))
+}
+
+pub(super) fn with_mut<R>(f: impl FnOnce(&mut Capture) -> R) -> R {
+    CAPTURE.lock(|capture| f(&mut capture.borrow_mut()))
+}
+
+/// Raised when an output is switched on, for captures triggered by it
+pub static OUTPUT_ENABLE_SIGNALS: [Signal<ThreadModeRawMutex, ()>; 2] =
+    [Signal::new(), Signal::new()];
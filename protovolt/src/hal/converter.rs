@@ -1,9 +1,10 @@
 use core::cell::RefCell;
 
 use defmt::*;
-use embassy_rp::gpio::{AnyPin, Level, Output};
+use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pull};
 use embassy_sync::blocking_mutex::Mutex;
-use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use embassy_sync::mutex::Mutex as AsyncMutex;
 use embassy_time::Timer;
 use embedded_hal::i2c::I2c;
 
@@ -21,34 +22,142 @@ mod tps55289 {
     pub const MODE: u8 = 0x06;
     pub const STATUS: u8 = 0x07;
 
-    pub fn cc_discharge_time(v_i: u16, v_f: u16) -> u64 {
-        // capacitance 590uF on output
-        // + extra
-        590 * (v_i as u64 - v_f as u64) / 100 + 20000
+    // CDC bits, each enabling the fault's indication on ~INT and in STATUS
+    pub const CDC_SC_MASK: u8 = 1 << 7;
+    pub const CDC_OCP_MASK: u8 = 1 << 6;
+    pub const CDC_OVP_MASK: u8 = 1 << 5;
+    /// Compensation set by a resistor on the CDC pin, left clear for CDC bits 2:0
+    pub const CDC_OPTION: u8 = 1 << 3;
+    pub const CDC_COMP_MASK: u8 = 0b0000_0111;
+
+    // MODE bits
+    pub const MODE_OE: u8 = 1 << 7;
+    pub const MODE_FSWDBL: u8 = 1 << 6;
+    pub const MODE_HICCUP: u8 = 1 << 5;
+    pub const MODE_DISCHG: u8 = 1 << 4;
+    /// External VCC supply, left clear for the internal regulator
+    pub const MODE_VCC: u8 = 1 << 3;
+    pub const MODE_I2CADD: u8 = 1 << 2;
+    pub const MODE_FPWM: u8 = 1 << 1;
+    /// Take VCC, I2CADD and PFM from the register instead of the MODE pin resistor
+    pub const MODE_REGISTER: u8 = 1 << 0;
+
+    // STATUS bits, cleared on read
+    pub const STATUS_SCP: u8 = 1 << 7;
+    pub const STATUS_OCP: u8 = 1 << 6;
+    pub const STATUS_OVP: u8 = 1 << 5;
+    pub const STATUS_MODE_MASK: u8 = 0b0000_0011;
+
+    /// ~INT is held low again right away while a fault persists
+    pub const INT_HOLDOFF_MS: u64 = 100;
+
+    // VOUT_SR bits 1:0, output slew rate during REF changes
+    pub const SR_MASK: u8 = 0b0000_0011;
+    pub const SR_1_25_MV_US: u8 = 0b00;
+
+    // VOUT_FS bits 1:0, internal feedback ratio
+    pub const INTFB_MASK: u8 = 0b0000_0011;
+
+    /// Feedback divisor of each INTFB setting, and the top of its output range
+    pub const FEEDBACK_DIVISOR: [u32; 4] = [625, 1250, 1875, 2500];
+    pub const FEEDBACK_RANGE_MV: u16 = 5000;
+
+    pub fn feedback_range(voltage: u16) -> Result<usize, ()> {
+        match voltage {
+            200..=5000 => Ok(0),
+            5001..=10000 => Ok(1),
+            10001..=15000 => Ok(2),
+            15001..=20000 => Ok(3),
+            _ => Err(()),
+        }
+    }
+
+    /// REF register value putting out `voltage` (mV) on feedback range `range`
+    pub fn ref_register(voltage: u16, range: usize) -> u16 {
+        let conv = voltage as u32 * 1000; // mV -> uV
+        let vref = (conv * 141 / FEEDBACK_DIVISOR[range]).saturating_sub(45_000);
+        (vref * 2 / 1129) as u16
+    }
+
+    /// Time for the output to slew between two voltages at 1.25 mV/us
+    pub fn slew_time(v_i: u16, v_f: u16) -> u64 {
+        // + settling margin
+        v_i.abs_diff(v_f) as u64 * 100 / 125 + 500
     }
 }
 
 use tps55289::*;
 
 use crate::hal::device::I2cDeviceWithAddr;
-use crate::hal::event::Channel;
+use crate::hal::event::{
+    Channel, ConverterFault, ConverterMode, ConverterRegion, ConverterStatus, SelfTestError,
+};
+
+/// Cable resistance compensated by one CDC step: 0.1 V at the 50 mV full
+/// scale over the 10 mΩ sense resistor, i.e. at 5 A
+pub const CDC_STEP: u16 = 20; // mΩ
+pub const CDC_STEPS_MAX: u16 = 7;
+
+/// Held across every REF and VOUT_FS write of a converter, so that a slew
+/// never interleaves with another one on the same output
+static SLEW_LOCKS: [AsyncMutex<ThreadModeRawMutex, ()>; 2] =
+    [AsyncMutex::new(()), AsyncMutex::new(())];
+
+fn address(channel: Channel) -> u8 {
+    match channel {
+        Channel::A => ADDR + 1,
+        Channel::B => ADDR,
+    }
+}
 
 pub trait Converter {
-    async fn init(&mut self) -> Result<(), ()>;
+    async fn init(&mut self) -> Result<(), SelfTestError>;
 
     fn enable(&mut self) -> Result<(), ()>;
     fn disable(&mut self) -> Result<(), ()>;
 
     fn get_enabled(&mut self) -> Result<bool, ()>;
     fn get_voltage(&mut self) -> Result<u16, ()>;
+    fn get_status(&mut self) -> Result<ConverterStatus, ()>;
+
+    fn set_mode(&mut self, mode: ConverterMode) -> Result<(), ()>;
+    fn set_cable_compensation(&mut self, resistance: u16) -> Result<(), ()>;
 
     async fn set_voltage(&mut self, voltage: u16) -> Result<(), ()>;
     fn set_current(&mut self, current: u16) -> Result<(), ()>;
 }
 
+/// EN line of a TPS55289, shared with the sense alert handler so that it can
+/// shut the converter down without going through the converter bus.
+///
+/// Pulling EN low resets the converter, which then needs a full `init`.
+pub struct ConverterEnable<'a> {
+    en: Mutex<ThreadModeRawMutex, RefCell<Output<'a>>>,
+}
+
+impl<'a> ConverterEnable<'a> {
+    pub fn new(enable_pin: AnyPin) -> Self {
+        Self {
+            en: Mutex::new(RefCell::new(Output::new(enable_pin, Level::Low))),
+        }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.en.lock(|en| en.borrow().is_set_high())
+    }
+
+    pub fn set(&self, high: bool) {
+        self.en.lock(|en| match high {
+            true => en.borrow_mut().set_high(),
+            false => en.borrow_mut().set_low(),
+        });
+    }
+}
+
 pub struct ConverterDevice<'a, M: RawMutex, BUS: I2c> {
     i2c: I2cDeviceWithAddr<'a, M, BUS>,
-    en: Output<'a>,
+    en: &'a ConverterEnable<'a>,
+    channel: Channel,
 }
 
 impl<'a, M, BUS> ConverterDevice<'a, M, BUS>
@@ -56,19 +165,22 @@ where
     M: RawMutex,
     BUS: I2c + 'a,
 {
-    pub fn new(enable_pin: AnyPin, mutex: &'a Mutex<M, RefCell<BUS>>, channel: Channel) -> Self {
-        let en = Output::new(enable_pin, Level::Low);
-
-        let address = match channel {
-            Channel::A => ADDR + 1,
-            Channel::B => ADDR,
-        };
-
+    pub fn new(
+        en: &'a ConverterEnable<'a>,
+        mutex: &'a Mutex<M, RefCell<BUS>>,
+        channel: Channel,
+    ) -> Self {
         Self {
-            i2c: I2cDeviceWithAddr::new(mutex, address),
-            en: en,
+            i2c: I2cDeviceWithAddr::new(mutex, address(channel)),
+            en,
+            channel,
         }
     }
+
+    fn update_mode(&mut self, update: impl FnOnce(u8) -> u8) -> Result<(), ()> {
+        let reg = self.i2c.read_reg_byte(MODE).map_err(|_| ())?;
+        self.i2c.write(&[MODE, update(reg)]).map_err(|_| ())
+    }
 }
 
 impl<'a, M, BUS> Converter for ConverterDevice<'a, M, BUS>
@@ -76,8 +188,13 @@ where
     M: RawMutex,
     BUS: I2c + 'a,
 {
-    async fn init(&mut self) -> Result<(), ()> {
-        self.en.set_high();
+    async fn init(&mut self) -> Result<(), SelfTestError> {
+        // Cycle EN on a retried init, resetting MODE to what the check below expects
+        if self.en.is_high() {
+            self.en.set(false);
+            Timer::after_millis(10).await;
+        }
+        self.en.set(true);
 
         Timer::after_millis(100).await; // Await controller start after EN/UVLO pulled high
 
@@ -86,7 +203,7 @@ where
         let mut regs = [0u8; 8];
         self.i2c.write_read(&[REF_LSB], &mut regs).map_err(|_| {
             warn!("start converter, i2c read error");
-            ()
+            SelfTestError::Bus
         })?;
 
         info!("regs {}", regs);
@@ -99,21 +216,29 @@ where
             info!("verified mode, status: 0b{:08b} 0b{:08b}", mode, status);
         } else {
             warn!("invalid mode, status");
-            return Err(());
+            return Err(SelfTestError::Register);
         }
 
-        self.disable()?;
+        let sr = self.i2c.read_reg_byte(VOUT_SR).map_err(|_| SelfTestError::Bus)?;
+        let sr = (sr & !SR_MASK) | SR_1_25_MV_US;
+        self.i2c.write(&[VOUT_SR, sr]).map_err(|_| SelfTestError::Bus)?;
+
+        let cdc = self.i2c.read_reg_byte(CDC).map_err(|_| SelfTestError::Bus)?;
+        let cdc = cdc | CDC_SC_MASK | CDC_OCP_MASK | CDC_OVP_MASK;
+        self.i2c.write(&[CDC, cdc]).map_err(|_| SelfTestError::Bus)?;
+
+        self.disable().map_err(|_| SelfTestError::Bus)?;
 
         Ok(())
     }
 
     fn enable(&mut self) -> Result<(), ()> {
-        self.i2c.write(&[MODE, 0b1011_0000]).map_err(|_| ())
+        self.update_mode(|reg| reg | MODE_OE)
     }
 
     fn disable(&mut self) -> Result<(), ()> {
         // self.set_voltage(0)?;
-        self.i2c.write(&[MODE, 0b0011_0000]).map_err(|_| ())
+        self.update_mode(|reg| reg & !MODE_OE)
     }
 
     fn get_enabled(&mut self) -> Result<bool, ()> {
@@ -124,68 +249,70 @@ where
     }
 
     fn get_voltage(&mut self) -> Result<u16, ()> {
-        let feedback_reg = self.i2c.read_reg_byte(VOUT_FS).map_err(|_| ())?;
-        let feedback_divisor = match feedback_reg & 3 {
-            0 => 625u32,
-            1 => 1250u32,
-            2 => 1875u32,
-            _ => 2500u32,
-        };
+        read_voltage(&mut self.i2c)
+    }
 
-        let mut read = [0u8; 2];
-        self.i2c.write_read(&[REF_LSB], &mut read).map_err(|_| ())?;
-        let (lsb, msb) = (read[0], read[1]);
+    fn get_status(&mut self) -> Result<ConverterStatus, ()> {
+        read_status(&mut self.i2c)
+    }
+
+    /// Configure everything in MODE but the output enable
+    ///
+    /// Register control of PFM also moves VCC and I2CADD off the MODE pin,
+    /// so both are written back as the board straps them.
+    fn set_mode(&mut self, mode: ConverterMode) -> Result<(), ()> {
+        let mut bits = MODE_REGISTER;
+        if address(self.channel) != ADDR {
+            bits |= MODE_I2CADD;
+        }
+        for (set, bit) in [
+            (mode.forced_pwm, MODE_FPWM),
+            (mode.hiccup, MODE_HICCUP),
+            (mode.discharge, MODE_DISCHG),
+            (mode.double_frequency, MODE_FSWDBL),
+        ] {
+            if set {
+                bits |= bit;
+            }
+        }
 
-        let voltage_reg = (msb as u32) << 8 | lsb as u32;
+        self.update_mode(|reg| (reg & MODE_OE) | bits)?;
 
-        let vref = voltage_reg * 1129 / 2;
-        let conv = (vref + 45_000) * feedback_divisor / 141_000;
+        info!("set mode 0b{:08b}", bits);
 
-        Ok(conv as u16)
+        Ok(())
     }
 
     /// Set TPS55289 output voltage
     /// * voltage: mV
+    ///
+    /// A live output stays enabled and slews to the new voltage.
     async fn set_voltage(&mut self, voltage: u16) -> Result<(), ()> {
-        let (feedback_divisor, feedback_reg) = match voltage {
-            200..=5000 => (625u32, 0u8),
-            5001..=10000 => (1250u32, 1u8),
-            10001..=15000 => (1875u32, 2u8),
-            15001..=20000 => (2500u32, 3u8),
-            _ => return Err(()),
-        };
+        let _slew = SLEW_LOCKS[self.channel as usize].lock().await;
 
-        let conv = voltage as u32 * 1000; // mV -> uV
-        let vref = conv * 141 / feedback_divisor - 45_000;
-        let reg = (vref * 2 / 1129) as u16;
-
-        let (msb, lsb) = ((reg >> 8) as u8, reg as u8 & 0xFF);
-
-        let was_enabled = self.get_enabled()?;
-        let prev_voltage = self.get_voltage()?;
-
-        self.disable()?;
-        if was_enabled && (prev_voltage > voltage) {
-            let us_delay = cc_discharge_time(prev_voltage, voltage);
-            info!(
-                "v initial {} mV, v final {} mV, discharge time {} us",
-                prev_voltage, voltage, us_delay
-            );
-            Timer::after_micros(us_delay).await;
+        if self.get_enabled()? {
+            return slew_voltage(&mut self.i2c, voltage, || false).await.map(|_| ());
         }
 
-        self.i2c.write(&[VOUT_FS, feedback_reg]).map_err(|_| ())?;
-        self.i2c.write(&[REF_LSB, lsb, msb]).map_err(|_| ())?;
+        let range = feedback_range(voltage)?;
+        write_feedback_range(&mut self.i2c, range)?;
+        write_ref(&mut self.i2c, ref_register(voltage, range))?;
 
-        if was_enabled {
-            self.enable()?;
-        };
+        info!("set voltage {} mV, range {}", voltage, range);
 
-        // let buf = ((msb as u16) << 8) | (lsb as u16);
-        info!(
-            "set voltage {} mV: MSB {:08b} LSB {:08b}",
-            voltage, msb, lsb
-        );
+        Ok(())
+    }
+
+    /// Set TPS55289 internal cable drop compensation
+    /// * resistance: mΩ, rounded down to whole CDC steps
+    fn set_cable_compensation(&mut self, resistance: u16) -> Result<(), ()> {
+        let steps = (resistance / CDC_STEP).min(CDC_STEPS_MAX) as u8;
+
+        let reg = self.i2c.read_reg_byte(CDC).map_err(|_| ())?;
+        let reg = (reg & !(CDC_OPTION | CDC_COMP_MASK)) | steps;
+        self.i2c.write(&[CDC, reg]).map_err(|_| ())?;
+
+        info!("set cable compensation {} mOhm: REG {:08b}", resistance, reg);
 
         Ok(())
     }
@@ -204,3 +331,202 @@ where
         Ok(())
     }
 }
+
+fn read_voltage<M: RawMutex, BUS: I2c>(
+    i2c: &mut I2cDeviceWithAddr<'_, M, BUS>,
+) -> Result<u16, ()> {
+    let feedback_reg = i2c.read_reg_byte(VOUT_FS).map_err(|_| ())?;
+    let feedback_divisor = FEEDBACK_DIVISOR[(feedback_reg & INTFB_MASK) as usize];
+
+    let mut read = [0u8; 2];
+    i2c.write_read(&[REF_LSB], &mut read).map_err(|_| ())?;
+    let (lsb, msb) = (read[0], read[1]);
+
+    let voltage_reg = (msb as u32) << 8 | lsb as u32;
+
+    let vref = voltage_reg * 1129 / 2;
+    let conv = (vref + 45_000) * feedback_divisor / 141_000;
+
+    Ok(conv as u16)
+}
+
+fn write_ref<M: RawMutex, BUS: I2c>(
+    i2c: &mut I2cDeviceWithAddr<'_, M, BUS>,
+    reg: u16,
+) -> Result<(), ()> {
+    let (msb, lsb) = ((reg >> 8) as u8, reg as u8);
+    i2c.write(&[REF_LSB, lsb, msb]).map_err(|_| ())
+}
+
+fn write_feedback_range<M: RawMutex, BUS: I2c>(
+    i2c: &mut I2cDeviceWithAddr<'_, M, BUS>,
+    range: usize,
+) -> Result<(), ()> {
+    let fs = i2c.read_reg_byte(VOUT_FS).map_err(|_| ())?;
+    let fs = (fs & !INTFB_MASK) | range as u8;
+    i2c.write(&[VOUT_FS, fs]).map_err(|_| ())
+}
+
+/// Move a live output to `voltage` (mV) without disabling it.
+///
+/// Crossing into another feedback range goes through the boundary voltage
+/// both ranges can put out, ordering the REF and VOUT_FS writes so that the
+/// output may briefly dip but never overshoots.
+///
+/// `superseded` is checked before every register write, and stops the slew
+/// where it is when set. Returns whether the slew got to `voltage`.
+async fn slew_voltage<M: RawMutex, BUS: I2c>(
+    i2c: &mut I2cDeviceWithAddr<'_, M, BUS>,
+    voltage: u16,
+    superseded: impl Fn() -> bool,
+) -> Result<bool, ()> {
+    let target_range = feedback_range(voltage)?;
+
+    let feedback_reg = i2c.read_reg_byte(VOUT_FS).map_err(|_| ())?;
+    let mut range = (feedback_reg & INTFB_MASK) as usize;
+    let mut present = read_voltage(i2c)?;
+
+    while range != target_range {
+        let (next, boundary) = match target_range > range {
+            true => (range + 1, FEEDBACK_RANGE_MV * (range as u16 + 1)),
+            false => (range - 1, FEEDBACK_RANGE_MV * range as u16),
+        };
+
+        // Ramp to the boundary on the current range first
+        if superseded() {
+            return Ok(false);
+        }
+        write_ref(i2c, ref_register(boundary, range))?;
+        Timer::after_micros(slew_time(present, boundary)).await;
+
+        // A larger divisor raises the output for the same REF: lower REF
+        // before switching up, switch down before raising REF. Stopping in
+        // between leaves the output below the boundary.
+        if superseded() {
+            return Ok(false);
+        }
+        if next > range {
+            write_ref(i2c, ref_register(boundary, next))?;
+            if superseded() {
+                return Ok(false);
+            }
+            write_feedback_range(i2c, next)?;
+        } else {
+            write_feedback_range(i2c, next)?;
+            if superseded() {
+                return Ok(false);
+            }
+            write_ref(i2c, ref_register(boundary, next))?;
+        }
+
+        range = next;
+        present = boundary;
+    }
+
+    if superseded() {
+        return Ok(false);
+    }
+    write_ref(i2c, ref_register(voltage, range))?;
+
+    debug!("slew voltage {} mV -> {} mV, range {}", present, voltage, range);
+
+    Ok(true)
+}
+
+fn read_status<M: RawMutex, BUS: I2c>(
+    i2c: &mut I2cDeviceWithAddr<'_, M, BUS>,
+) -> Result<ConverterStatus, ()> {
+    let reg = i2c.read_reg_byte(STATUS).map_err(|_| ())?;
+
+    Ok(ConverterStatus {
+        current_limit: reg & STATUS_OCP != 0,
+        short_circuit: reg & STATUS_SCP != 0,
+        over_voltage: reg & STATUS_OVP != 0,
+        region: match reg & STATUS_MODE_MASK {
+            0b00 => Some(ConverterRegion::Boost),
+            0b01 => Some(ConverterRegion::Buck),
+            0b10 => Some(ConverterRegion::BuckBoost),
+            _ => None,
+        },
+    })
+}
+
+/// ~INT line of a TPS55289, with its own handle on the shared bus to read
+/// STATUS back as soon as the line asserts
+pub struct ConverterInterrupt<'a, M: RawMutex, BUS: I2c> {
+    i2c: I2cDeviceWithAddr<'a, M, BUS>,
+    int: Input<'a>,
+    channel: Channel,
+}
+
+impl<'a, M, BUS> ConverterInterrupt<'a, M, BUS>
+where
+    M: RawMutex,
+    BUS: I2c + 'a,
+{
+    pub fn new(int_pin: AnyPin, mutex: &'a Mutex<M, RefCell<BUS>>, channel: Channel) -> Self {
+        // Open-drain, active low
+        let int = Input::new(int_pin, Pull::Up);
+
+        Self {
+            i2c: I2cDeviceWithAddr::new(mutex, address(channel)),
+            int,
+            channel,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Wait for ~INT and decode the fault it reports
+    pub async fn wait_fault(&mut self) -> Result<Option<ConverterFault>, ()> {
+        self.int.wait_for_low().await;
+
+        let status = read_status(&mut self.i2c);
+        Timer::after_millis(INT_HOLDOFF_MS).await;
+
+        status.map(|status| status.fault())
+    }
+}
+
+/// Handle on a TPS55289 reference, for stepping a live output over time
+/// from outside the main loop
+pub struct ConverterRamp<'a, M: RawMutex, BUS: I2c> {
+    i2c: I2cDeviceWithAddr<'a, M, BUS>,
+    channel: Channel,
+}
+
+impl<'a, M, BUS> ConverterRamp<'a, M, BUS>
+where
+    M: RawMutex,
+    BUS: I2c + 'a,
+{
+    pub fn new(mutex: &'a Mutex<M, RefCell<BUS>>, channel: Channel) -> Self {
+        Self {
+            i2c: I2cDeviceWithAddr::new(mutex, address(channel)),
+            channel,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// * mV
+    pub fn get_voltage(&mut self) -> Result<u16, ()> {
+        read_voltage(&mut self.i2c)
+    }
+
+    /// * voltage: mV
+    ///
+    /// Returns whether the step completed, or was cut short by `superseded`
+    pub async fn step(
+        &mut self,
+        voltage: u16,
+        superseded: impl Fn() -> bool,
+    ) -> Result<bool, ()> {
+        let _slew = SLEW_LOCKS[self.channel as usize].lock().await;
+        slew_voltage(&mut self.i2c, voltage, superseded).await
+    }
+}
@@ -6,6 +6,7 @@ use embassy_rp::Peripheral;
 use embassy_rp::gpio::Output;
 use embassy_rp::spi::{self, Instance, Spi};
 use embassy_rp::gpio::{AnyPin, Level};
+use embassy_rp::pwm::{self, ChannelAPin, Pwm, Slice};
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_time::Delay;
@@ -25,6 +26,8 @@ pub mod st7789 {
     pub const HEIGHT: u16 = 320;
     pub const COLOR_INVERSION: ColorInversion = ColorInversion::Inverted;
     pub const ORIENTATION: Rotation = Rotation::Deg270;
+
+    pub const BACKLIGHT_PWM_TOP: u16 = 0x0FFF;
 }
 
 pub struct DisplayInterface<'d, T: Instance> {
@@ -77,3 +80,32 @@ where
         }
     }
 }
+
+pub struct Backlight<'d> {
+    pwm: Pwm<'d>,
+    config: pwm::Config,
+}
+
+impl<'d> Backlight<'d> {
+    /// Backlight starts off, so nothing shows before the first clear
+    pub fn new<T: Slice>(
+        slice: impl Peripheral<P = T> + 'd,
+        pin: impl Peripheral<P = impl ChannelAPin<T>> + 'd,
+    ) -> Self {
+        let mut config = pwm::Config::default();
+        config.top = st7789::BACKLIGHT_PWM_TOP;
+        config.compare_a = 0;
+
+        Self {
+            pwm: Pwm::new_output_a(slice, pin, config.clone()),
+            config: config,
+        }
+    }
+
+    /// * brightness: %
+    pub fn set_brightness(&mut self, brightness: u8) {
+        let top = st7789::BACKLIGHT_PWM_TOP as u32;
+        self.config.compare_a = (top * brightness.min(100) as u32 / 100) as u16;
+        self.pwm.set_config(&self.config);
+    }
+}
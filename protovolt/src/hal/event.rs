@@ -1,29 +1,301 @@
 use core::{array::IntoIter, iter::FilterMap};
 
-use embassy_time::Duration;
+use embassy_time::{Duration, Instant};
 
 use defmt::*;
 
-use crate::app::{DecimalPrecision, SetSelect};
+use crate::app::{
+    DecimalPrecision, SetSelect,
+    config::Config,
+    scpi::{ScpiCommand, ScpiError, ScpiResponse},
+    settings::Settings,
+};
+use crate::hal::calibration::Calibration;
 
 #[derive(Debug)]
 pub enum HardwareEvent {
     PowerOn,
+    ConfigLoaded(Config),
 
-    PowerDeliveryReady(PowerType),
-    SenseReady(Result<(), ()>),
-    ConverterReady(Result<(), ()>),
+    PowerDeliveryReady(Result<PowerType, ChipFault>),
+    SenseReady(SelfTest),
+    ConverterReady(SelfTest),
 
     StartMainInterface,
 
     ReadoutAcquired(Channel, Readout),
+    ConverterStatus(Channel, ConverterStatus),
+    ConverterFault(Channel, ConverterFault),
+    /// The INA226 alert tripped and pulled the converter's EN low
+    SenseAlert(Channel),
+    /// The INA226 alert pulled a live converter's EN low without an over-current
+    SenseAlertSpurious(Channel),
+    ConverterRestarted(Channel),
+    /// Burst capture complete, with the number of samples it kept
+    CaptureDone(Channel, usize),
+
+    Fault(FaultReason),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Chip {
+    Ina226(Channel),
+    Tps55289(Channel),
+    Stusb4500,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum SelfTestError {
+    Bus,
+    Identity,
+    Register,
+    Timeout,
+}
+
+impl SelfTestError {
+    /// Numeric code shown on the fault screen
+    pub fn code(self) -> u8 {
+        match self {
+            SelfTestError::Bus => 0x01,
+            SelfTestError::Identity => 0x02,
+            SelfTestError::Register => 0x03,
+            SelfTestError::Timeout => 0x04,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct ChipFault {
+    pub chip: Chip,
+    pub error: SelfTestError,
+}
+
+/// Per-channel outcome of a subsystem self-test
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SelfTest {
+    pub ch_a: Option<ChipFault>,
+    pub ch_b: Option<ChipFault>,
+}
+
+impl SelfTest {
+    pub fn channel_ok(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::A => self.ch_a.is_none(),
+            Channel::B => self.ch_b.is_none(),
+        }
+    }
+
+    pub fn first_fault(&self) -> Option<ChipFault> {
+        self.ch_a.or(self.ch_b)
+    }
+
+    pub fn faults(&self) -> impl Iterator<Item = ChipFault> {
+        self.ch_a.into_iter().chain(self.ch_b)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum FaultReason {
+    // Boot self-tests
+    SelfTest(ChipFault),
+
+    // Runtime bus failures
+    SenseBus(Channel),
+    ConverterBus(Channel),
+
+    Protection(Channel, ProtectionTrip),
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryAction {
+    #[default]
+    RetryInit,
+    ContinueDegraded,
+    DisableOutputs,
+}
+
+impl RecoveryAction {
+    pub fn next(self) -> Self {
+        match self {
+            RecoveryAction::RetryInit => RecoveryAction::ContinueDegraded,
+            RecoveryAction::ContinueDegraded => RecoveryAction::DisableOutputs,
+            RecoveryAction::DisableOutputs => RecoveryAction::RetryInit,
+        }
+    }
+
+    pub fn prev(self) -> Self {
+        match self {
+            RecoveryAction::RetryInit => RecoveryAction::DisableOutputs,
+            RecoveryAction::ContinueDegraded => RecoveryAction::RetryInit,
+            RecoveryAction::DisableOutputs => RecoveryAction::ContinueDegraded,
+        }
+    }
+}
+
+/// Every INA226 conversion since the previous readout of a channel, combined.
+/// The values are their mean, the spreads keep the peaks in between.
 #[derive(Clone, Copy, Debug)]
 pub struct Readout {
     pub voltage: f32,
     pub current: f32,
     pub power: f32,
+
+    pub voltage_spread: Spread,
+    pub current_spread: Spread,
+    pub power_spread: Spread,
+    /// Conversions combined
+    pub samples: u32,
+
+    /// Uncalibrated INA226 readings, for diagnostics
+    pub raw_voltage: f32,
+    pub raw_current: f32,
+
+    /// When the reading was taken, for integrating it over time
+    pub time: Instant,
+}
+
+/// Charge and energy delivered by a channel
+#[derive(Clone, Copy, Debug)]
+pub struct EnergyCount {
+    /// mAh
+    pub charge: f32,
+    /// mWh
+    pub energy: f32,
+    /// Time integrated with the output on
+    pub elapsed: Duration,
+}
+
+/// Readouts the statistics view is taken over
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum StatisticsWindow {
+    /// Latest readouts only
+    Rolling,
+    /// Every readout since the last reset
+    SinceReset,
+}
+
+/// Spread of one measured quantity
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Spread {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+}
+
+/// Statistics of a channel's readouts, taken while its output is on
+#[derive(Clone, Copy, Debug)]
+pub struct Statistics {
+    pub window: StatisticsWindow,
+    pub voltage: Spread,
+    pub current: Spread,
+    pub power: Spread,
+    pub count: u32,
+}
+
+/// Decoded TPS55289 STATUS register
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConverterStatus {
+    /// Output current has reached IOUT_LIMIT since the last read
+    pub current_limit: bool,
+    pub short_circuit: bool,
+    pub over_voltage: bool,
+    /// `None` on the reserved encoding
+    pub region: Option<ConverterRegion>,
+}
+
+impl ConverterStatus {
+    /// Most severe of the flagged faults
+    pub fn fault(&self) -> Option<ConverterFault> {
+        if self.short_circuit {
+            Some(ConverterFault::ShortCircuit)
+        } else if self.over_voltage {
+            Some(ConverterFault::OverVoltage)
+        } else if self.current_limit {
+            Some(ConverterFault::OverCurrent)
+        } else {
+            None
+        }
+    }
+}
+
+/// Fault reported by a TPS55289 on its ~INT line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum ConverterFault {
+    ShortCircuit,
+    OverCurrent,
+    OverVoltage,
+}
+
+/// Operating region the TPS55289 is switching in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum ConverterRegion {
+    Boost,
+    Buck,
+    BuckBoost,
+}
+
+/// TPS55289 MODE register options, everything but the output enable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConverterMode {
+    /// Forced PWM at light load, PFM otherwise
+    pub forced_pwm: bool,
+    /// Restart in hiccup cycles on over-current, latch off until re-enabled otherwise
+    pub hiccup: bool,
+    /// Discharge the output while it is disabled
+    pub discharge: bool,
+    /// Double the switching frequency in buck-boost operation
+    pub double_frequency: bool,
+}
+
+impl Default for ConverterMode {
+    fn default() -> Self {
+        Self {
+            forced_pwm: false,
+            hiccup: true,
+            discharge: true,
+            double_frequency: false,
+        }
+    }
+}
+
+/// INA226 averaging and conversion time trade-off, see `measure::SenseConfig`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SensePreset {
+    Fast,
+    #[default]
+    Normal,
+    LowNoise,
+}
+
+/// What starts a burst capture, see `capture::Capture`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureTrigger {
+    /// The channel's output being switched on
+    OutputEnable,
+    /// Readout current at or above this, A
+    Current(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum RegulationMode {
+    ConstantVoltage,
+    ConstantCurrent,
+}
+
+/// Estimated input power against what the source can deliver
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Format)]
+pub enum BudgetState {
+    #[default]
+    Normal,
+    Warning,
+    Exceeded,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum ProtectionTrip {
+    OverVoltage,
+    OverCurrent,
+    ShortCircuit,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -58,6 +330,12 @@ pub enum Change {
     Released,
 }
 
+/// Pair of arrow buttons held together
+pub enum Chord {
+    UpDown,
+    LeftRight,
+}
+
 pub enum InterfaceEvent {
     ButtonUp,
     ButtonDown,
@@ -67,14 +345,22 @@ pub enum InterfaceEvent {
     ButtonSwitch(Change),
     ButtonSettings(Change),
     ButtonChannel(Channel),
+    ButtonChord(Chord),
 }
 
 pub enum AppEvent {
     Hardware(HardwareEvent),
     Interface(InterfaceEvent),
+    Remote(Result<ScpiCommand, ScpiError>),
 }
 
 pub enum HardwareTask {
+    LoadConfig,
+    SaveConfig(Config),
+    SaveCalibration(Channel, Calibration),
+    /// Put a calibration into effect without persisting it
+    ApplyCalibration(Channel, Calibration),
+
     // Initialization sequence + self-checks
     EnablePowerDelivery,
     EnableSense,
@@ -87,12 +373,28 @@ pub enum HardwareTask {
     UpdateConverterState(Channel, bool),
     UpdateConverterVoltage(Channel, f32),
     UpdateConverterCurrent(Channel, f32),
+    UpdateConverterMode(Channel, ConverterMode),
+    /// Cable resistance, mΩ
+    UpdateConverterCompensation(Channel, u16),
+    /// Target voltage, rate in V/s
+    RampConverterVoltage(Channel, f32, f32),
+    ReadConverterStatus(Channel),
+    /// Re-initialise a converter reset by a sense alert
+    RestartConverter(Channel),
+    UpdateSenseConfig([SensePreset; 2]),
+    /// Over-current alert limit per channel, A
+    UpdateSenseAlert([Option<f32>; 2]),
+    StartCapture(Channel, CaptureTrigger),
+    CancelCapture,
 
     // DelayedInterfaceEvent(Duration, InterfaceEvent),
     DelayedHardwareEvent(Duration, HardwareEvent),
+
+    /// Answer to the remote query being handled, `None` when it failed
+    RemoteResponse(Option<ScpiResponse>),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Format)]
 pub enum Channel {
     A,
     B,
@@ -120,6 +422,8 @@ pub enum ChannelFocus {
     UnselectedActive,
     SelectedInactive,
     UnselectedInactive,
+    SelectedFault,
+    UnselectedFault,
 }
 
 #[derive(Clone, Copy)]
@@ -138,24 +442,83 @@ pub enum DisplayTask {
     // Splash Screen
     SetupSplash,
 
-    ConfirmPowerDelivery(PowerType),
-    ConfirmSense(Result<(), ()>),
-    ConfirmConverter(Result<(), ()>),
+    ConfirmPowerDelivery(Result<PowerType, ChipFault>),
+    ConfirmSense(SelfTest),
+    ConfirmConverter(SelfTest),
 
     // Main Readout
     SetupMain(PowerType, Limits, Limits),
 
     // Updates
     UpdateReadout(Channel, Readout),
-    UpdateSetpoint(Channel, Limits, Option<SetSelect>, ConfirmState, Option<DecimalPrecision>),
+    /// Statistics in place of the readout, while that view is on
+    UpdateStatistics(Channel, Statistics),
+    /// Energy counters in place of the readout, while that view is on
+    UpdateEnergy(Channel, EnergyCount),
+    /// Setpoint with voltage and current flagged when staged but not yet applied
+    UpdateSetpoint(
+        Channel,
+        Limits,
+        [bool; 2],
+        Option<SetSelect>,
+        ConfirmState,
+        Option<DecimalPrecision>,
+    ),
     UpdateChannelFocus(ChannelFocus, ChannelFocus),
     UpdateSetState(Channel, SetState, Option<SetSelect>, ConfirmState),
+    UpdateProtection(Channel, Option<ProtectionTrip>),
+    UpdateChannelOffline(Channel),
+    UpdateRegulation(Channel, Option<RegulationMode>),
+    UpdateRegion(Channel, Option<ConverterRegion>),
+    UpdateChannelShed(Channel),
+    UpdateBudget(u8, BudgetState),
 
     // Navbar
     UpdateButton(ConfirmState, Option<FunctionButton>),
 
+    // Fault
+    SetupFault(FaultReason, RecoveryAction),
+    UpdateRecoveryAction(RecoveryAction),
+
     // Settings
-    // SetupSettings,
+    SetupSettings,
+    UpdateSettings(Settings, usize),
+    SetupDeviceInfo(DeviceInfo),
+    SetupCalibration(Channel),
+    UpdateCalibration(CalibrationStep),
+    SetupCapture(Channel, CaptureTrigger),
+    /// Plot the capture the sense task completed
+    ShowCapture,
+    UpdateBrightness(u8, u8),
+}
+
+#[derive(Clone, Copy)]
+pub struct DeviceInfo {
+    pub power_type: PowerType,
+    pub power_delivery: Option<Result<(), ChipFault>>,
+    pub sense: Option<SelfTest>,
+    pub converter: Option<SelfTest>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationQuantity {
+    Voltage,
+    Current,
+}
+
+/// Calibration wizard step, with the DMM value entered so far
+#[derive(Clone, Copy)]
+pub struct CalibrationStep {
+    pub channel: Channel,
+    pub index: usize,
+    pub count: usize,
+    pub quantity: CalibrationQuantity,
+    /// Programmed output voltage or current limit, V or A
+    pub point: f32,
+    pub measured: f32,
+    pub precision: DecimalPrecision,
+    /// The last run fitted an implausible calibration and was restarted
+    pub rejected: bool,
 }
 
 // pub struct AppTask {
@@ -168,7 +531,9 @@ pub enum Task {
     Display(DisplayTask),
 }
 
-const APP_TASK_SIZE_LIMIT: usize = 8;
+/// Enough for the largest composition, resuming the main screen with both
+/// channels tripped or regulating (18). Running over it is a bug.
+const APP_TASK_SIZE_LIMIT: usize = 24;
 
 pub struct AppTask {
     pub tasks: [Option<Task>; APP_TASK_SIZE_LIMIT],
@@ -206,6 +571,7 @@ impl AppTaskBuilder {
         while let Some(task) = iter.next() {
             if *i >= APP_TASK_SIZE_LIMIT {
                 warn!("AppTaskBuilder tasks overflow from extend");
+                debug_assert!(false, "AppTaskBuilder tasks overflow from extend");
                 break;
             }
 
@@ -222,6 +588,7 @@ impl AppTaskBuilder {
             self.inner.count += 1;
         } else {
             warn!("AppTaskBuilder tasks overflow");
+            debug_assert!(false, "AppTaskBuilder tasks overflow");
         };
 
         self
@@ -1,4 +1,4 @@
-use crate::hal::event::{Change, Channel, InterfaceEvent};
+use crate::hal::event::{Change, Channel, Chord, InterfaceEvent};
 use embassy_rp::gpio::{AnyPin, Input, Level, Output, Pull};
 use embassy_time::Duration;
 
@@ -10,6 +10,9 @@ pub mod matrix {
 
     pub const DEBOUNCE_THRESHOLD: u16 = 3;
     pub const POLL_TIME_MS: u64 = 10;
+
+    /// Polls an arrow press is held back for, in case its pair completes a chord
+    pub const CHORD_WINDOW: u8 = 5;
 }
 
 pub struct ButtonsInterface<'a> {
@@ -18,6 +21,8 @@ pub struct ButtonsInterface<'a> {
     current_state: [bool; matrix::N_BUTTONS],
     prev_state: [bool; matrix::N_BUTTONS],
     debounce: [u16; matrix::N_BUTTONS],
+    /// Arrow pressed and polls left before it is sent on its own
+    pending_arrow: Option<(usize, u8)>,
 }
 
 impl ButtonsInterface<'_> {
@@ -31,6 +36,7 @@ impl ButtonsInterface<'_> {
             current_state: [false; matrix::N_BUTTONS],
             prev_state: [false; matrix::N_BUTTONS],
             debounce: [0; matrix::N_BUTTONS],
+            pending_arrow: None,
         }
     }
 
@@ -84,11 +90,14 @@ impl ButtonsInterface<'_> {
                     1 => Some(InterfaceEvent::ButtonSwitch(change)),
                     2 => Some(InterfaceEvent::ButtonEnter(change)),
 
-                    3 => emit_on_press(change, InterfaceEvent::ButtonRight),
-                    6 => emit_on_press(change, InterfaceEvent::ButtonLeft),
-
-                    4 => emit_on_press(change, InterfaceEvent::ButtonUp),
-                    5 => emit_on_press(change, InterfaceEvent::ButtonDown),
+                    // Arrows wait out the chord window, a newer one sends the last
+                    3..=6 => match change {
+                        Change::Pressed => self
+                            .pending_arrow
+                            .replace((i, matrix::CHORD_WINDOW))
+                            .and_then(|(k, _)| arrow_event(k)),
+                        Change::Released => None,
+                    },
 
                     7 => emit_on_press(change, InterfaceEvent::ButtonChannel(Channel::B)),
                     8 => emit_on_press(change, InterfaceEvent::ButtonChannel(Channel::A)),
@@ -97,12 +106,42 @@ impl ButtonsInterface<'_> {
             }
         }
 
+        // The press completing an arrow pair reports the chord instead, and
+        // neither arrow is sent
+        for (a, b, chord) in [(4, 5, Chord::UpDown), (3, 6, Chord::LeftRight)] {
+            let pressed = |k: usize| self.current_state[k] && !self.prev_state[k];
+            if self.current_state[a] && self.current_state[b] && (pressed(a) || pressed(b)) {
+                button_event = Some(InterfaceEvent::ButtonChord(chord));
+                self.pending_arrow = None;
+            }
+        }
+
+        // Released early or held past the window, no chord is coming either way
+        if let Some((k, polls)) = self.pending_arrow {
+            if polls > 0 && self.current_state[k] {
+                self.pending_arrow = Some((k, polls - 1));
+            } else if button_event.is_none() {
+                button_event = arrow_event(k);
+                self.pending_arrow = None;
+            }
+        }
+
         self.prev_state = self.current_state;
 
         button_event
     }
 }
 
+fn arrow_event(k: usize) -> Option<InterfaceEvent> {
+    match k {
+        3 => Some(InterfaceEvent::ButtonRight),
+        4 => Some(InterfaceEvent::ButtonUp),
+        5 => Some(InterfaceEvent::ButtonDown),
+        6 => Some(InterfaceEvent::ButtonLeft),
+        _ => None,
+    }
+}
+
 fn emit_on_press(change: Change, event: InterfaceEvent) -> Option<InterfaceEvent> {
     match change {
         Change::Pressed => Some(event),
@@ -16,6 +16,7 @@ pub mod ws2812 {
 pub struct LedsInterface<'a, PIO: Instance> {
     led: PioWs2812<'a, PIO, 0, LED_COUNT>,
     data: [RGB8; LED_COUNT],
+    brightness: u8,
 }
 
 pub enum LedsColor {
@@ -47,11 +48,20 @@ where
         Self {
             led: ws2812,
             data: [RGB8::default(); LED_COUNT],
+            brightness: 100,
         }
     }
 
     pub async fn refresh(&mut self) {
-        self.led.write(&self.data).await;
+        let scale = |c: u8| (c as u16 * self.brightness as u16 / 100) as u8;
+        let data = self.data.map(|c| RGB8::new(scale(c.r), scale(c.g), scale(c.b)));
+
+        self.led.write(&data).await;
+    }
+
+    /// * brightness: % of the colour scheme intensity
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness.min(100);
     }
 
     pub fn update_color(&mut self, color: LedsColor) {
@@ -1,7 +1,16 @@
 use core::cell::RefCell;
 
 use defmt::*;
-use embassy_sync::blocking_mutex::{raw::RawMutex, Mutex};
+use embassy_rp::gpio::{AnyPin, Input, Pull};
+use embassy_time::Duration;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{
+    blocking_mutex::{
+        Mutex,
+        raw::{RawMutex, ThreadModeRawMutex},
+    },
+    signal::Signal,
+};
 use embedded_hal::i2c::I2c;
 
 #[allow(dead_code)]
@@ -23,6 +32,149 @@ mod ina226 {
 
     pub const MANUFACTURER_ID: u8 = 0xFE;
     pub const DIE_ID: u8 = 0xFF;
+
+    // CONFIG fields
+    pub const CONFIG_AVG_SHIFT: u16 = 9;
+    pub const CONFIG_VBUSCT_SHIFT: u16 = 6;
+    pub const CONFIG_VSHCT_SHIFT: u16 = 3;
+    /// Bits 14-12 read back as 0b100
+    pub const CONFIG_FIXED: u16 = 0x4000;
+    /// Shunt and bus voltage, continuous
+    pub const CONFIG_MODE_CONTINUOUS: u16 = 0b111;
+
+    // ENABLE (Mask/Enable) bits
+    /// Alert on the shunt voltage rising above ALERT_LIMIT
+    pub const ENABLE_SOL: u16 = 1 << 15;
+    /// Alert function flag, the limit was crossed
+    pub const ENABLE_AFF: u16 = 1 << 4;
+    /// Conversion ready flag, cleared by reading ENABLE or writing CONFIG
+    pub const ENABLE_CVRF: u16 = 1 << 3;
+    /// Hold ALERT and AFF until ENABLE is read
+    pub const ENABLE_LEN: u16 = 1 << 0;
+
+    pub const SHUNT_VOLTAGE_LSB: f32 = 2.5e-6; // V
+}
+
+/// Samples averaged into each INA226 result
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Averaging {
+    X1,
+    X4,
+    X16,
+    X64,
+    X128,
+    X256,
+    X512,
+    X1024,
+}
+
+impl Averaging {
+    fn code(self) -> u16 {
+        self as u16
+    }
+
+    fn count(self) -> u32 {
+        match self {
+            Averaging::X1 => 1,
+            Averaging::X4 => 4,
+            Averaging::X16 => 16,
+            Averaging::X64 => 64,
+            Averaging::X128 => 128,
+            Averaging::X256 => 256,
+            Averaging::X512 => 512,
+            Averaging::X1024 => 1024,
+        }
+    }
+}
+
+/// INA226 ADC conversion time of one sample
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConversionTime {
+    Us140,
+    Us204,
+    Us332,
+    Us588,
+    Us1100,
+    Us2116,
+    Us4156,
+    Us8244,
+}
+
+impl ConversionTime {
+    fn code(self) -> u16 {
+        self as u16
+    }
+
+    fn micros(self) -> u32 {
+        match self {
+            ConversionTime::Us140 => 140,
+            ConversionTime::Us204 => 204,
+            ConversionTime::Us332 => 332,
+            ConversionTime::Us588 => 588,
+            ConversionTime::Us1100 => 1100,
+            ConversionTime::Us2116 => 2116,
+            ConversionTime::Us4156 => 4156,
+            ConversionTime::Us8244 => 8244,
+        }
+    }
+}
+
+/// INA226 CONFIG register contents, always measuring continuously
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SenseConfig {
+    pub averaging: Averaging,
+    pub bus_conversion: ConversionTime,
+    pub shunt_conversion: ConversionTime,
+}
+
+impl SenseConfig {
+    /// Fastest the INA226 converts, for burst captures
+    pub const CAPTURE: Self = Self {
+        averaging: Averaging::X1,
+        bus_conversion: ConversionTime::Us140,
+        shunt_conversion: ConversionTime::Us140,
+    };
+
+    fn register(&self) -> u16 {
+        CONFIG_FIXED
+            | self.averaging.code() << CONFIG_AVG_SHIFT
+            | self.bus_conversion.code() << CONFIG_VBUSCT_SHIFT
+            | self.shunt_conversion.code() << CONFIG_VSHCT_SHIFT
+            | CONFIG_MODE_CONTINUOUS
+    }
+
+    /// Time between fresh results, every averaged sample converting both inputs
+    pub fn period(&self) -> Duration {
+        let sample = self.bus_conversion.micros() + self.shunt_conversion.micros();
+        Duration::from_micros((sample * self.averaging.count()) as u64)
+    }
+}
+
+impl From<SensePreset> for SenseConfig {
+    fn from(preset: SensePreset) -> Self {
+        match preset {
+            // 2.2 ms, follows load steps at the cost of noise
+            SensePreset::Fast => SenseConfig {
+                averaging: Averaging::X1,
+                bus_conversion: ConversionTime::Us1100,
+                shunt_conversion: ConversionTime::Us1100,
+            },
+            // 35 ms
+            SensePreset::Normal => SenseConfig {
+                averaging: Averaging::X16,
+                bus_conversion: ConversionTime::Us1100,
+                shunt_conversion: ConversionTime::Us1100,
+            },
+            // 271 ms
+            SensePreset::LowNoise => SenseConfig {
+                averaging: Averaging::X64,
+                bus_conversion: ConversionTime::Us2116,
+                shunt_conversion: ConversionTime::Us2116,
+            },
+        }
+    }
 }
 
 const R_SHUNT: f32 = 0.010; // 10mR
@@ -35,17 +187,47 @@ const CAL: [u8; 2] = compute_cal(CURRENT_LSB, R_SHUNT);
 const fn compute_cal(current_lsb: f32, shunt_resistance: f32) -> [u8; 2] {
     let cal = 0.00512 / (current_lsb * shunt_resistance);
 
-    // TODO: figure out rounding or truncating?
-    // also, uncertainty computation on either method. (currently truncating)
+    // Truncating: the residual gain error is taken out by the channel's
+    // readout current calibration, see hal::calibration
     (cal as u16).to_be_bytes()
 }
 
 use ina226::*;
 
-use crate::hal::{device::I2cDeviceWithAddr, event::Channel};
+use crate::hal::{
+    converter::ConverterEnable,
+    device::I2cDeviceWithAddr,
+    event::{Channel, SelfTestError, SensePreset},
+};
+
+/// Mask/Enable register contents. Reading it clears CVRF and releases a
+/// latched ALERT with its AFF, so every flag is handled from the one read.
+#[derive(Clone, Copy)]
+pub struct SenseFlags(u16);
+
+impl SenseFlags {
+    /// A conversion finished since the last read
+    pub fn conversion_ready(self) -> bool {
+        self.0 & ENABLE_CVRF != 0
+    }
+
+    /// The alert limit was crossed
+    pub fn alert(self) -> bool {
+        self.0 & ENABLE_AFF != 0
+    }
+}
+
+/// AFF taken by the sense task's flag reads, for the alert task to act on
+pub static SENSE_ALERT_SIGNALS: [Signal<ThreadModeRawMutex, ()>; 2] =
+    [Signal::new(), Signal::new()];
 
 pub trait Measure {
-    fn init(&mut self) -> Result<(), ()>;
+    fn init(&mut self, config: SenseConfig) -> Result<(), SelfTestError>;
+    fn configure(&mut self, config: SenseConfig) -> Result<(), ()>;
+    /// * current: A, or `None` to disarm the alert
+    fn set_alert_limit(&mut self, current: Option<f32>) -> Result<(), ()>;
+    /// Mask/Enable flags, cleared by this very read
+    fn read_flags(&mut self) -> Result<SenseFlags, ()>;
 
     #[allow(dead_code)]
     fn read_shunt_voltage(&mut self) -> Result<f32, ()>;
@@ -59,19 +241,21 @@ pub struct MeasureDevice<'a, M: RawMutex, BUS: I2c> {
     i2c: I2cDeviceWithAddr<'a, M, BUS>
 }
 
+fn address(channel: Channel) -> u8 {
+    match channel {
+        Channel::A => ADDR + 1,
+        Channel::B => ADDR,
+    }
+}
+
 impl <'a, M, BUS> MeasureDevice<'a, M, BUS>
 where 
     M: RawMutex,
     BUS: I2c + 'a,
 {
     pub fn new(mutex: &'a Mutex<M, RefCell<BUS>>, channel: Channel) -> Self {
-        let address = match channel {
-            Channel::A => ADDR + 1,
-            Channel::B => ADDR,
-        };
-
         Self {
-            i2c: I2cDeviceWithAddr::new(mutex, address)
+            i2c: I2cDeviceWithAddr::new(mutex, address(channel))
         }
     }
 }
@@ -81,16 +265,16 @@ where
     M: RawMutex,
     BUS: I2c + 'a,
 {
-    fn init(&mut self) -> Result<(), ()>{
+    fn init(&mut self, config: SenseConfig) -> Result<(), SelfTestError>{
         let mut manufacturer_id = [0u8; 2];
         self.i2c
             .write_read( &[MANUFACTURER_ID], &mut manufacturer_id)
-            .map_err(|_| ())?;
+            .map_err(|_| SelfTestError::Bus)?;
 
         let id = u16::from_be_bytes(manufacturer_id);
         if id != 0x5449 {
             error!("Manufacturer ID mismatch: got 0x{:04X}", id);
-            return Err(());
+            return Err(SelfTestError::Identity);
         }
         info!("verfied manufaturer id:  got 0x{:04X}", id);
         // TODO: verify DIE_ID also
@@ -100,16 +284,43 @@ where
 
         self.i2c
             .write( &[CALIBRATION, CAL[0], CAL[1]])
-            .map_err(|_| ())?;
+            .map_err(|_| SelfTestError::Bus)?;
 
         // TODO: verify CAL is correctly written
 
+        self.configure(config).map_err(|_| SelfTestError::Bus)
+    }
+
+    fn configure(&mut self, config: SenseConfig) -> Result<(), ()> {
+        let [high, low] = config.register().to_be_bytes();
+        self.i2c.write(&[CONFIG, high, low]).map_err(|_| ())?;
+
+        debug!("sense config 0x{:04X}", config.register());
         Ok(())
     }
 
+    fn set_alert_limit(&mut self, current: Option<f32>) -> Result<(), ()> {
+        let Some(current) = current else {
+            return self.i2c.write(&[ENABLE, 0, 0]).map_err(|_| ());
+        };
+
+        // Compared against the shunt voltage, so the limit does not depend on CAL
+        let limit = (current * R_SHUNT / SHUNT_VOLTAGE_LSB).clamp(0.0, i16::MAX as f32) as u16;
+        let [high, low] = limit.to_be_bytes();
+        self.i2c.write(&[ALERT_LIMIT, high, low]).map_err(|_| ())?;
+
+        let [high, low] = (ENABLE_SOL | ENABLE_LEN).to_be_bytes();
+        self.i2c.write(&[ENABLE, high, low]).map_err(|_| ())
+    }
+
+    fn read_flags(&mut self) -> Result<SenseFlags, ()> {
+        let flags = self.i2c.read_reg_word(ENABLE).map_err(|_| ())?;
+        Ok(SenseFlags(flags))
+    }
+
     fn read_shunt_voltage(&mut self) -> Result<f32, ()> {
         let reg = self.i2c.read_reg_word(SHUNT_VOLTAGE).map_err(|_| ())?;
-        Ok((reg as i16 as f32) * 2.5e-6)
+        Ok((reg as i16 as f32) * SHUNT_VOLTAGE_LSB)
     }
 
     fn read_bus_voltage(&mut self) -> Result<f32, ()> {
@@ -129,3 +340,81 @@ where
         // TODO: move 1.25mV LSB out into INA226 constants
     }
 }
+
+/// ALERT line of an INA226 (SENS_INT_A/B), armed with the channel's OCP
+/// limit, and the EN line of the converter it shuts down.
+///
+/// The INA226 drives a single limit function on ALERT, so only over-current
+/// is caught here; over-voltage is left to the TPS55289 OVP and the readout
+/// checks. The alert compares every averaged result, so it reacts within one
+/// conversion period of the channel's sense preset.
+pub struct SenseAlert<'a, M: RawMutex, BUS: I2c> {
+    i2c: I2cDeviceWithAddr<'a, M, BUS>,
+    alert: Input<'a>,
+    en: &'a ConverterEnable<'a>,
+    channel: Channel,
+}
+
+impl<'a, M, BUS> SenseAlert<'a, M, BUS>
+where
+    M: RawMutex,
+    BUS: I2c + 'a,
+{
+    pub fn new(
+        alert_pin: AnyPin,
+        mutex: &'a Mutex<M, RefCell<BUS>>,
+        en: &'a ConverterEnable<'a>,
+        channel: Channel,
+    ) -> Self {
+        // Open-drain, active low
+        let alert = Input::new(alert_pin, Pull::Up);
+
+        Self {
+            i2c: I2cDeviceWithAddr::new(mutex, address(channel)),
+            alert,
+            en,
+            channel,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Wait for the alert, shutting the converter down the moment it fires.
+    /// Returns whether it was an over-current trip, rather than an alert
+    /// without AFF that reset a live converter for nothing.
+    pub async fn wait_trip(&mut self) -> bool {
+        let forwarded = &SENSE_ALERT_SIGNALS[self.channel as usize];
+
+        loop {
+            // The sense task's flag read may release ALERT and take AFF with it
+            let alert = select(self.alert.wait_for_low(), forwarded.wait()).await;
+
+            // Before anything goes over the bus, which may be busy or stuck
+            let was_on = self.en.is_high();
+            self.en.set(false);
+
+            if let Either::Second(()) = alert {
+                return true;
+            }
+
+            // Reading ENABLE releases the latched ALERT line
+            match self.i2c.read_reg_word(ENABLE) {
+                Ok(flags) if flags & ENABLE_AFF != 0 => return true,
+                Ok(_) if forwarded.try_take().is_some() => return true,
+                Ok(_) => warn!("sense {} alert without AFF", self.channel),
+                Err(_) => {
+                    warn!("sense {} alert flags unreadable", self.channel);
+                    return true;
+                }
+            }
+
+            // Not a trip, wait for the line to come back up before looking again
+            self.alert.wait_for_high().await;
+            if was_on {
+                return false;
+            }
+        }
+    }
+}
@@ -1,25 +1,38 @@
 use core::cell::RefCell;
 
-use embassy_rp::gpio::AnyPin;
+use defmt::*;
 use embassy_sync::{
     blocking_mutex::{
         Mutex,
         raw::{NoopRawMutex, RawMutex, ThreadModeRawMutex},
     },
     channel::{Channel, Receiver, Sender},
+    signal::Signal,
 };
-use embassy_time::{Duration, Ticker};
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use embedded_hal::i2c::I2c;
 
 use crate::{
-    StaticI2c1,
+    StaticI2c0, StaticI2c1,
+    app::config::Config,
     hal::{
-        converter::{Converter, ConverterDevice},
-        event::{Channel as OutputChannel, HardwareEvent},
-        measure::{Measure, MeasureDevice},
+        calibration::Calibration,
+        converter::{
+            Converter, ConverterDevice, ConverterEnable, ConverterInterrupt, ConverterRamp,
+        },
+        event::{
+            CaptureTrigger, Channel as OutputChannel, Chip, ChipFault, ConverterMode,
+            ConverterStatus, FaultReason, HardwareEvent, Limits, PowerType, SelfTest,
+            SelfTestError, SensePreset,
+        },
+        measure::{Measure, MeasureDevice, SENSE_ALERT_SIGNALS, SenseAlert, SenseConfig},
+        power::{PowerDelivery, PowerDeliveryDevice},
+        storage::{FlashStorage, StorageError},
     },
 };
 
+pub mod calibration;
+pub mod capture;
 pub mod display;
 pub mod event;
 pub mod interface;
@@ -30,10 +43,23 @@ mod device;
 pub mod converter;
 pub mod measure;
 pub mod power;
+pub mod storage;
+pub mod usb;
+
+/// Time the source gets to attach and settle a PD contract at boot
+const POWER_DELIVERY_TIMEOUT: Duration = Duration::from_millis(1000);
+const POWER_DELIVERY_POLL: Duration = Duration::from_millis(50);
+
+/// Interval between reference steps of a voltage ramp
+const RAMP_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct Hal<'a, M: RawMutex, BUS: I2c> {
+    power: PowerDeliveryDevice<'a, M, BUS>,
+
     ch_a: ConverterDevice<'a, M, BUS>,
     ch_b: ConverterDevice<'a, M, BUS>,
+
+    storage: FlashStorage<'a>,
 }
 
 impl<'a, M, BUS> Hal<'a, M, BUS>
@@ -43,26 +69,188 @@ where
 {
     pub fn new(
         converter_bus: &'a Mutex<M, RefCell<BUS>>,
-        ch_a_enable: AnyPin,
-        ch_b_enable: AnyPin,
+        ch_a_enable: &'a ConverterEnable<'a>,
+        ch_b_enable: &'a ConverterEnable<'a>,
+        storage: FlashStorage<'a>,
     ) -> Self {
         Self {
+            power: PowerDeliveryDevice::new(converter_bus),
+
             ch_a: ConverterDevice::new(ch_a_enable, converter_bus, OutputChannel::A),
             ch_b: ConverterDevice::new(ch_b_enable, converter_bus, OutputChannel::B),
+
+            storage,
+        }
+    }
+
+    pub fn load_config(&mut self) -> Config {
+        if !self.storage.is_mounted() {
+            if let Err(e) = self.storage.mount() {
+                warn!("storage: mount failed: {}", e);
+                return Config::default();
+            }
+        }
+
+        let config = Config::load(&mut self.storage);
+        for (channel, calibration) in [OutputChannel::A, OutputChannel::B]
+            .into_iter()
+            .zip(config.calibration)
+        {
+            if let Some(calibration) = calibration {
+                calibration::set(channel, calibration);
+            }
+        }
+
+        config
+    }
+
+    pub fn save_config(&mut self, config: &Config) -> Result<(), StorageError> {
+        if !self.storage.is_mounted() {
+            self.storage.mount()?;
+        }
+
+        config.save(&mut self.storage)
+    }
+
+    /// Put a calibration into effect for the running session only
+    pub fn apply_calibration(&mut self, channel: OutputChannel, value: Calibration) {
+        calibration::set(channel, value);
+    }
+
+    /// Put a new calibration into effect and persist it
+    pub fn save_calibration(
+        &mut self,
+        channel: OutputChannel,
+        value: Calibration,
+    ) -> Result<(), StorageError> {
+        self.apply_calibration(channel, value);
+
+        let mut config = Config::default();
+        config.calibration[channel as usize] = Some(value);
+        self.save_config(&config)
+    }
+
+    /// Read the contract the STUSB4500 negotiated, falling back to the Type-C
+    /// current advertisement when the source does not speak PD
+    pub async fn enable_power_delivery(&mut self) -> Result<PowerType, ChipFault> {
+        let fault = |error| ChipFault {
+            chip: Chip::Stusb4500,
+            error,
+        };
+
+        self.power.init().map_err(fault)?;
+        self.log_power_delivery_config();
+
+        let deadline = Instant::now() + POWER_DELIVERY_TIMEOUT;
+        let attached = loop {
+            let attached = self
+                .power
+                .get_attached()
+                .map_err(|_| fault(SelfTestError::Bus))?;
+
+            if attached {
+                let contract = self
+                    .power
+                    .get_contract()
+                    .map_err(|_| fault(SelfTestError::Register))?;
+
+                if let Some(limits) = contract {
+                    info!("pd contract {}V {}A", limits.voltage, limits.current);
+                    return Ok(PowerType::PowerDelivery(limits));
+                }
+            }
+
+            if Instant::now() >= deadline {
+                break attached;
+            }
+            Timer::after(POWER_DELIVERY_POLL).await;
+        };
+
+        if !attached {
+            warn!("pd: no source attached");
+            return Err(fault(SelfTestError::Timeout));
+        }
+
+        let current = self
+            .power
+            .get_typec_current()
+            .map_err(|_| fault(SelfTestError::Bus))?;
+        info!("no pd contract, type-c current {}A", current);
+
+        Ok(PowerType::Standard(Limits {
+            voltage: 5.0,
+            current,
+        }))
+    }
+
+    fn log_power_delivery_config(&mut self) {
+        let pd = &mut self.power;
+
+        for pdo in 1..4u8 {
+            info!("({}) voltage (V) {}", pdo, pd.get_voltage(pdo));
+            info!("({}) current (A) {}", pdo, pd.get_current(pdo));
+            info!("({}) lower voltage tolerance (%) {}", pdo, pd.get_lower_voltage_limit(pdo));
+            info!("({}) upper voltage tolerance (%) {}", pdo, pd.get_upper_voltage_limit(pdo));
         }
+        info!("pdo number {}", pd.get_pdo_number());
+        info!("flex current {}", pd.get_flex_current());
+        info!("external power {}", pd.get_external_power());
+        info!("usb comm capable {}", pd.get_usb_comm_capable());
+        info!("configuration ok gpio {}", pd.get_config_ok_gpio());
+        info!("extra gpio pin config {}", pd.get_gpio_ctrl());
+        info!("power out >5V only {}", pd.get_power_above_5v_only());
+        info!("operating current {}", pd.get_req_src_current());
     }
 
     pub async fn enable_sense(&mut self) {
         SENSE_CHANNEL.send(SenseEvent::Enable).await;
     }
 
+    pub async fn update_sense_config(&mut self, presets: [SensePreset; 2]) {
+        let configs = presets.map(SenseConfig::from);
+        SENSE_CHANNEL.send(SenseEvent::Configure(configs)).await;
+    }
+
+    /// * limits: A, or `None` to disarm the channel's alert
+    pub async fn update_sense_alert(&mut self, limits: [Option<f32>; 2]) {
+        SENSE_CHANNEL.send(SenseEvent::AlertLimits(limits)).await;
+    }
+
     pub async fn enable_readout_loop(&mut self) {
         SENSE_CHANNEL.send(SenseEvent::StartReadoutLoop).await;
     }
 
-    pub async fn enable_converter(&mut self) -> Result<(), ()> {
-        self.ch_a.init().await?;
-        self.ch_b.init().await
+    pub async fn start_capture(&mut self, channel: OutputChannel, trigger: CaptureTrigger) {
+        SENSE_CHANNEL.send(SenseEvent::Capture(channel, trigger)).await;
+    }
+
+    pub async fn cancel_capture(&mut self) {
+        SENSE_CHANNEL.send(SenseEvent::CancelCapture).await;
+    }
+
+    pub async fn enable_converter(&mut self) -> SelfTest {
+        let (a, b) = (self.ch_a.init().await, self.ch_b.init().await);
+
+        let fault = |channel, error| ChipFault {
+            chip: Chip::Tps55289(channel),
+            error,
+        };
+
+        SelfTest {
+            ch_a: a.err().map(|e| fault(OutputChannel::A, e)),
+            ch_b: b.err().map(|e| fault(OutputChannel::B, e)),
+        }
+    }
+
+    /// Bring a converter back up after a sense alert pulled its EN low
+    pub async fn restart_converter(&mut self, channel: OutputChannel) -> Result<(), ()> {
+        RAMP_SIGNALS[channel as usize].signal(RampCommand::Stop);
+
+        let ch = match channel {
+            OutputChannel::A => &mut self.ch_a,
+            OutputChannel::B => &mut self.ch_b,
+        };
+        ch.init().await.map_err(|e| warn!("converter {} restart failed: {}", channel, e))
     }
 
     pub async fn update_converter_state(
@@ -74,17 +262,62 @@ where
             OutputChannel::A => &mut self.ch_a,
             OutputChannel::B => &mut self.ch_b,
         };
+        if !active {
+            RAMP_SIGNALS[channel as usize].signal(RampCommand::Stop);
+        }
+
         match active {
-            true => ch.enable(),
+            true => {
+                ch.enable()?;
+                capture::OUTPUT_ENABLE_SIGNALS[channel as usize].signal(());
+                Ok(())
+            }
             false => ch.disable(),
         }
     }
 
+    pub async fn update_converter_mode(
+        &mut self,
+        channel: OutputChannel,
+        mode: ConverterMode,
+    ) -> Result<(), ()> {
+        match channel {
+            OutputChannel::A => self.ch_a.set_mode(mode),
+            OutputChannel::B => self.ch_b.set_mode(mode),
+        }
+    }
+
+    /// * resistance: mΩ
+    pub async fn update_converter_compensation(
+        &mut self,
+        channel: OutputChannel,
+        resistance: u16,
+    ) -> Result<(), ()> {
+        match channel {
+            OutputChannel::A => self.ch_a.set_cable_compensation(resistance),
+            OutputChannel::B => self.ch_b.set_cable_compensation(resistance),
+        }
+    }
+
+    pub async fn read_converter_status(
+        &mut self,
+        channel: OutputChannel,
+    ) -> Result<ConverterStatus, ()> {
+        match channel {
+            OutputChannel::A => self.ch_a.get_status(),
+            OutputChannel::B => self.ch_b.get_status(),
+        }
+    }
+
     pub async fn update_converter_voltage(
         &mut self,
         channel: OutputChannel,
         voltage: f32,
     ) -> Result<(), ()> {
+        // A direct write takes over from any ramp still running
+        RAMP_SIGNALS[channel as usize].signal(RampCommand::Stop);
+
+        let voltage = calibration::get(channel).set_voltage.apply(voltage);
         let voltage = (voltage * 1000.0) as u16;
         match channel {
             OutputChannel::A => self.ch_a.set_voltage(voltage).await,
@@ -92,11 +325,22 @@ where
         }
     }
 
+    /// Hand a live output over to its ramp task
+    /// * rate: V/s
+    pub fn ramp_converter_voltage(&mut self, channel: OutputChannel, voltage: f32, rate: f32) {
+        let voltage = calibration::get(channel).set_voltage.apply(voltage);
+        RAMP_SIGNALS[channel as usize].signal(RampCommand::Start {
+            voltage: (voltage * 1000.0) as u16,
+            rate: (rate * 1000.0) as u32,
+        });
+    }
+
     pub async fn update_converter_current(
         &mut self,
         channel: OutputChannel,
         current: f32,
     ) -> Result<(),()> {
+        let current = calibration::get(channel).set_current.apply(current);
         let current = (current * 1000.0) as u16;
         match channel {
             OutputChannel::A => self.ch_a.set_current(current),
@@ -123,9 +367,160 @@ where
     }
 }
 
+pub type StaticConverterInterrupt = ConverterInterrupt<'static, NoopRawMutex, StaticI2c0>;
+
+/// Forward every fault a converter raises on its ~INT line
+#[embassy_executor::task(pool_size = 2)]
+pub async fn poll_converter_fault(
+    mut interrupt: StaticConverterInterrupt,
+    data_channel: Sender<'static, ThreadModeRawMutex, HardwareEvent, 32>,
+) {
+    let channel = interrupt.channel();
+
+    loop {
+        match interrupt.wait_fault().await {
+            Ok(Some(fault)) => {
+                data_channel
+                    .send(HardwareEvent::ConverterFault(channel, fault))
+                    .await
+            }
+            Ok(None) => {}
+            Err(()) => {
+                data_channel
+                    .send(HardwareEvent::Fault(FaultReason::ConverterBus(channel)))
+                    .await
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum RampCommand {
+    /// * voltage: mV
+    /// * rate: mV/s
+    Start { voltage: u16, rate: u32 },
+    Stop,
+}
+
+pub static RAMP_SIGNALS: [Signal<ThreadModeRawMutex, RampCommand>; 2] =
+    [Signal::new(), Signal::new()];
+
+pub type StaticConverterRamp = ConverterRamp<'static, NoopRawMutex, StaticI2c0>;
+
+/// Step a converter's reference towards the latest ramp target, so that the
+/// main loop never waits on a ramp
+#[embassy_executor::task(pool_size = 2)]
+pub async fn ramp_converter(
+    mut ramp: StaticConverterRamp,
+    data_channel: Sender<'static, ThreadModeRawMutex, HardwareEvent, 32>,
+) {
+    let channel = ramp.channel();
+    let signal = &RAMP_SIGNALS[channel as usize];
+
+    loop {
+        let mut command = signal.wait().await;
+
+        // Tracked here rather than read back, REF steps are coarser than a slow ramp's
+        let mut present = match ramp.get_voltage() {
+            Ok(voltage) => voltage,
+            Err(()) => {
+                data_channel
+                    .send(HardwareEvent::Fault(FaultReason::ConverterBus(channel)))
+                    .await;
+                continue;
+            }
+        };
+
+        while let RampCommand::Start { voltage, rate } = command {
+            let step = (rate * RAMP_INTERVAL.as_millis() as u32 / 1000).max(1);
+            let step = step.min(u16::MAX as u32) as u16;
+
+            let next = match present < voltage {
+                true => present.saturating_add(step).min(voltage),
+                false => present.saturating_sub(step).max(voltage),
+            };
+
+            // A new target or a stop cuts the step short before its next write
+            let stepped = ramp.step(next, || signal.signaled()).await;
+            let stepped = match stepped {
+                Ok(true) => Ok(next),
+                // Carry on from wherever the registers were left
+                Ok(false) => ramp.get_voltage(),
+                Err(()) => Err(()),
+            };
+            let Ok(stepped) = stepped else {
+                data_channel
+                    .send(HardwareEvent::Fault(FaultReason::ConverterBus(channel)))
+                    .await;
+                break;
+            };
+            present = stepped;
+
+            if signal.signaled() {
+                command = signal.try_take().unwrap_or(command);
+                continue;
+            }
+
+            if present == voltage {
+                debug!("channel {} ramp done at {} mV", channel, present);
+                break;
+            }
+
+            Timer::after(RAMP_INTERVAL).await;
+
+            // A new target or a stop takes over from the ramp in progress
+            command = signal.try_take().unwrap_or(command);
+        }
+    }
+}
+
 pub enum SenseEvent {
     Enable,
     StartReadoutLoop,
+    /// Applied right away to running channels, and kept for the next init
+    Configure([SenseConfig; 2]),
+    /// Over-current alert limits in A, kept like `Configure`
+    AlertLimits([Option<f32>; 2]),
+    /// Switch a channel to burst capture. Its readouts carry on from the capture
+    /// samples, at the usual readout rate.
+    Capture(OutputChannel, CaptureTrigger),
+    CancelCapture,
+}
+
+/// Pause between capture samples, only there to let the other tasks run.
+/// The I2C transfers pace the capture at about one sample per ms.
+const CAPTURE_YIELD: Duration = Duration::from_micros(100);
+
+/// Interval between readouts of a channel converting faster than that. Every
+/// conversion in between is combined into the readout, none is dropped.
+const READOUT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Conversion-ready checks per conversion period, once a readout is due
+const READY_CHECKS_PER_PERIOD: u32 = 8;
+const READY_CHECK_INTERVAL_MIN: Duration = Duration::from_millis(2);
+
+/// Check often enough to pick a result up shortly after it lands
+fn ready_check_interval(config: &SenseConfig) -> Duration {
+    (config.period() / READY_CHECKS_PER_PERIOD).max(READY_CHECK_INTERVAL_MIN)
+}
+
+/// Latest result of a channel, if the INA226 finished one since the last read
+fn read_fresh<M: RawMutex, BUS: I2c>(
+    ch: &mut MeasureDevice<'_, M, BUS>,
+    channel: OutputChannel,
+) -> Result<Option<(f32, f32, f32)>, ()> {
+    let flags = ch.read_flags()?;
+
+    // The read released a latched ALERT, the alert task still has to act on it
+    if flags.alert() {
+        SENSE_ALERT_SIGNALS[channel as usize].signal(());
+    }
+
+    if !flags.conversion_ready() {
+        return Ok(None);
+    }
+
+    Ok(Some((ch.read_bus_voltage()?, ch.read_current()?, ch.read_power()?)))
 }
 
 pub static SENSE_CHANNEL: Channel<ThreadModeRawMutex, SenseEvent, 1> = Channel::new();
@@ -136,51 +531,375 @@ pub async fn poll_sense(
     sense_channel: Receiver<'static, ThreadModeRawMutex, SenseEvent, 1>,
     data_channel: Sender<'static, ThreadModeRawMutex, HardwareEvent, 32>,
 ) {
-    match sense_channel.receive().await {
-        SenseEvent::Enable => {}
-        _ => return,
-    };
-
-    let (a, b) = (sense.ch_a.init(), sense.ch_b.init());
-    if a.is_ok() && b.is_ok() {
-        data_channel.send(HardwareEvent::SenseReady(Ok(()))).await;
-    } else {
-        data_channel.send(HardwareEvent::SenseReady(Err(()))).await;
-        return;
-    };
-
-    match sense_channel.receive().await {
-        SenseEvent::StartReadoutLoop => {}
-        _ => return,
-    };
-
-    let mut ticker = Ticker::every(Duration::from_hz(5)); // 100ms
+    let mut readout_loop = false;
+    let mut sense_ok = [false; 2];
+    let mut bus_ok = [true; 2];
+    let mut configs = [SenseConfig::from(SensePreset::default()); 2];
+    let mut alert_limits = [None; 2];
+    let mut active_capture: Option<(OutputChannel, CaptureTrigger)> = None;
+    // Next flag read per channel, paced by its conversion period
+    let mut next_check = [Instant::MIN; 2];
+    // Conversions are combined until the channel's next readout is due
+    let mut combined = [CombinedReadout::default(); 2];
+    let mut next_readout = [Instant::MIN; 2];
+
+    let mut ticker = Ticker::every(READY_CHECK_INTERVAL_MIN);
     loop {
+        // Block until enabled, but keep listening for a re-init while reading out
+        let event = match readout_loop {
+            true => sense_channel.try_receive().ok(),
+            false => Some(sense_channel.receive().await),
+        };
+
+        match event {
+            Some(SenseEvent::Enable) => {
+                readout_loop = false;
+                active_capture = None;
+
+                let (a, b) = (sense.ch_a.init(configs[0]), sense.ch_b.init(configs[1]));
+                sense_ok = [a.is_ok(), b.is_ok()];
+                apply_alert_limits(sense, &sense_ok, &alert_limits);
+
+                let fault = |channel, error| ChipFault {
+                    chip: Chip::Ina226(channel),
+                    error,
+                };
+                let result = SelfTest {
+                    ch_a: a.err().map(|e| fault(OutputChannel::A, e)),
+                    ch_b: b.err().map(|e| fault(OutputChannel::B, e)),
+                };
+                data_channel.send(HardwareEvent::SenseReady(result)).await;
+                continue;
+            }
+            Some(SenseEvent::StartReadoutLoop) => {
+                readout_loop = true;
+                bus_ok = [true; 2];
+                combined = Default::default();
+                ticker.reset();
+            }
+            Some(SenseEvent::Configure(next)) => {
+                configs = next;
+
+                // A capturing channel gets its config back once the capture is done
+                let capturing = active_capture.map(|(channel, _)| channel as usize);
+                for (k, ch) in [&mut sense.ch_a, &mut sense.ch_b].into_iter().enumerate() {
+                    if sense_ok[k] && capturing != Some(k) && ch.configure(configs[k]).is_err() {
+                        warn!("sense {} config write failed", k);
+                    }
+                }
+
+                // Nothing to read out before the first init
+                if !readout_loop {
+                    continue;
+                }
+            }
+            Some(SenseEvent::AlertLimits(next)) => {
+                alert_limits = next;
+                apply_alert_limits(sense, &sense_ok, &alert_limits);
+
+                if !readout_loop {
+                    continue;
+                }
+            }
+            Some(SenseEvent::Capture(channel, trigger)) => {
+                let k = channel as usize;
+                if let Some((previous, _)) = active_capture.take() {
+                    restore_config(sense, previous, configs[previous as usize]);
+                }
+
+                let ch = sense_channel_mut(sense, channel);
+                if sense_ok[k] && ch.configure(SenseConfig::CAPTURE).is_ok() {
+                    info!("sense {} capture armed", channel);
+                    capture::with_mut(|capture| capture.start());
+                    capture::OUTPUT_ENABLE_SIGNALS[k].reset();
+                    active_capture = Some((channel, trigger));
+                } else if sense_ok[k] {
+                    warn!("sense {} capture config write failed", channel);
+                    bus_ok[k] = false;
+                    data_channel
+                        .send(HardwareEvent::Fault(FaultReason::SenseBus(channel)))
+                        .await;
+                }
+
+                if !readout_loop {
+                    continue;
+                }
+            }
+            Some(SenseEvent::CancelCapture) => {
+                if let Some((channel, _)) = active_capture.take() {
+                    restore_config(sense, channel, configs[channel as usize]);
+                    ticker.reset();
+                }
+
+                if !readout_loop {
+                    continue;
+                }
+            }
+            None => {}
+        }
+
+        if let Some((channel, trigger)) = active_capture {
+            let k = channel as usize;
+
+            match capture_sample(sense, channel, trigger) {
+                Ok((false, (v, i))) => {
+                    // Keep protection and statistics fed while the trigger is awaited
+                    combined[k].add(channel, v, i, v * i);
+
+                    let now = Instant::now();
+                    if now >= next_readout[k] {
+                        next_readout[k] = now + READOUT_INTERVAL;
+                        if let Some(readout) = combined[k].take() {
+                            data_channel
+                                .send(HardwareEvent::ReadoutAcquired(channel, readout))
+                                .await;
+                        }
+                    }
+                }
+                Ok((true, _)) => {
+                    active_capture = None;
+                    restore_config(sense, channel, configs[k]);
+                    ticker.reset();
+
+                    let len = capture::with(|capture| capture.len());
+                    info!("sense {} capture done, {} samples", channel, len);
+                    data_channel.send(HardwareEvent::CaptureDone(channel, len)).await;
+                }
+                Err(()) => {
+                    active_capture = None;
+                    restore_config(sense, channel, configs[k]);
+                    ticker.reset();
+
+                    bus_ok[k] = false;
+                    data_channel
+                        .send(HardwareEvent::Fault(FaultReason::SenseBus(channel)))
+                        .await;
+                }
+            }
+        }
+
+        // The capturing channel reads out from its samples above
+        let capturing = active_capture.map(|(channel, _)| channel as usize);
         let channels = [OutputChannel::A, OutputChannel::B];
-        for event_ch in channels.iter() {
+        for (k, event_ch) in channels.iter().enumerate() {
+            // Channels that failed their self-test stay offline until the next init
+            if !sense_ok[k] || capturing == Some(k) {
+                continue;
+            }
+
+            let now = Instant::now();
+            if now < next_check[k] {
+                continue;
+            }
+
             let ch = match event_ch {
                 OutputChannel::A => &mut sense.ch_a,
                 OutputChannel::B => &mut sense.ch_b,
             };
 
-            let v = ch.read_bus_voltage();
-            let i = ch.read_current();
-            let p = ch.read_power();
+            let result = read_fresh(ch, *event_ch);
+            next_check[k] = now + ready_check_interval(&configs[k]);
 
-            if let (Ok(v), Ok(i), Ok(p)) = (v, i, p) {
-                data_channel
-                    .send(HardwareEvent::ReadoutAcquired(
-                        *event_ch,
-                        event::Readout {
-                            voltage: v,
-                            current: i,
-                            power: p,
-                        },
-                    ))
-                    .await;
+            match result {
+                Ok(Some((v, i, p))) => {
+                    bus_ok[k] = true;
+                    combined[k].add(*event_ch, v, i, p);
+
+                    if now >= next_readout[k] {
+                        next_readout[k] = now + READOUT_INTERVAL;
+                        if let Some(readout) = combined[k].take() {
+                            data_channel
+                                .send(HardwareEvent::ReadoutAcquired(*event_ch, readout))
+                                .await;
+                        }
+                    }
+                }
+                // Still converting, the result in the registers was read already
+                Ok(None) => {}
+                Err(()) if bus_ok[k] => {
+                    // Only report the transition, not every failed poll
+                    bus_ok[k] = false;
+                    data_channel
+                        .send(HardwareEvent::Fault(FaultReason::SenseBus(*event_ch)))
+                        .await;
+                }
+                Err(()) => {}
             }
         }
 
-        ticker.next().await;
+        match active_capture {
+            Some(_) => Timer::after(CAPTURE_YIELD).await,
+            None => ticker.next().await,
+        }
+    }
+}
+
+fn sense_channel_mut<'s>(
+    sense: &'s mut HalSense<'static, NoopRawMutex, StaticI2c1>,
+    channel: OutputChannel,
+) -> &'s mut MeasureDevice<'static, NoopRawMutex, StaticI2c1> {
+    match channel {
+        OutputChannel::A => &mut sense.ch_a,
+        OutputChannel::B => &mut sense.ch_b,
+    }
+}
+
+fn restore_config(
+    sense: &mut HalSense<'static, NoopRawMutex, StaticI2c1>,
+    channel: OutputChannel,
+    config: SenseConfig,
+) {
+    if sense_channel_mut(sense, channel).configure(config).is_err() {
+        warn!("sense {} config write failed", channel);
+    }
+}
+
+/// Record one capture sample, straight from the registers since the INA226
+/// converts faster than the bus reads. Returns whether the capture is complete,
+/// along with the raw bus voltage and current of the sample.
+fn capture_sample(
+    sense: &mut HalSense<'static, NoopRawMutex, StaticI2c1>,
+    channel: OutputChannel,
+    trigger: CaptureTrigger,
+) -> Result<(bool, (f32, f32)), ()> {
+    let ch = sense_channel_mut(sense, channel);
+    let (v, i) = (ch.read_bus_voltage()?, ch.read_current()?);
+    let time = Instant::now();
+
+    let calibration = calibration::get(channel);
+    let (voltage, current) = (
+        calibration.readout_voltage.apply(v),
+        calibration.readout_current.apply(i),
+    );
+
+    let output_enable = &capture::OUTPUT_ENABLE_SIGNALS[channel as usize];
+    let complete = capture::with_mut(|capture| {
+        capture.push(time, voltage, current);
+
+        if !capture.is_triggered() {
+            let fired = match trigger {
+                CaptureTrigger::OutputEnable => output_enable.try_take().is_some(),
+                CaptureTrigger::Current(threshold) => current >= threshold,
+            };
+            if fired {
+                capture.trigger();
+            }
+        }
+
+        capture.is_complete()
+    });
+
+    Ok((complete, (v, i)))
+}
+
+fn apply_alert_limits(
+    sense: &mut HalSense<'static, NoopRawMutex, StaticI2c1>,
+    sense_ok: &[bool; 2],
+    limits: &[Option<f32>; 2],
+) {
+    let channels = [OutputChannel::A, OutputChannel::B];
+    for (k, ch) in [&mut sense.ch_a, &mut sense.ch_b].into_iter().enumerate() {
+        if !sense_ok[k] {
+            continue;
+        }
+
+        // The INA226 compares its own, uncalibrated reading
+        let calibration = calibration::get(channels[k]).readout_current;
+        let limit = limits[k].map(|limit| calibration.invert(limit));
+
+        if ch.set_alert_limit(limit).is_err() {
+            warn!("sense {} alert write failed", channels[k]);
+        }
+    }
+}
+
+pub type StaticSenseAlert = SenseAlert<'static, NoopRawMutex, StaticI2c1>;
+
+/// Report every over-current shutdown an INA226 alert carried out
+#[embassy_executor::task(pool_size = 2)]
+pub async fn poll_sense_alert(
+    mut alert: StaticSenseAlert,
+    data_channel: Sender<'static, ThreadModeRawMutex, HardwareEvent, 32>,
+) {
+    let channel = alert.channel();
+
+    loop {
+        match alert.wait_trip().await {
+            true => {
+                warn!("sense {} alert, converter shut down", channel);
+                data_channel.send(HardwareEvent::SenseAlert(channel)).await;
+            }
+            false => data_channel.send(HardwareEvent::SenseAlertSpurious(channel)).await,
+        }
+    }
+}
+
+/// Conversions of a channel taken since its last readout
+#[derive(Clone, Copy, Default)]
+struct CombinedReadout {
+    count: u32,
+    /// Calibrated voltage, current and power
+    min: [f32; 3],
+    max: [f32; 3],
+    sum: [f32; 3],
+    /// Uncalibrated voltage and current
+    raw_sum: [f32; 2],
+}
+
+impl CombinedReadout {
+    fn add(&mut self, channel: OutputChannel, v: f32, i: f32, p: f32) {
+        let calibration = calibration::get(channel);
+        let (voltage, current) = (
+            calibration.readout_voltage.apply(v),
+            calibration.readout_current.apply(i),
+        );
+
+        // The INA226 power register multiplies the raw readings
+        let power = match calibration == Calibration::IDENTITY {
+            true => p,
+            false => voltage * current,
+        };
+
+        let values = [voltage, current, power];
+        if self.count == 0 {
+            (self.min, self.max) = (values, values);
+        }
+        for (k, value) in values.into_iter().enumerate() {
+            self.min[k] = self.min[k].min(value);
+            self.max[k] = self.max[k].max(value);
+            self.sum[k] += value;
+        }
+        self.raw_sum[0] += v;
+        self.raw_sum[1] += i;
+        self.count += 1;
+    }
+
+    /// Readout of everything added so far, starting over for the next one
+    fn take(&mut self) -> Option<event::Readout> {
+        let combined = core::mem::take(self);
+        if combined.count == 0 {
+            return None;
+        }
+
+        let n = combined.count as f32;
+        let spread = |k: usize| event::Spread {
+            min: combined.min[k],
+            max: combined.max[k],
+            mean: combined.sum[k] / n,
+        };
+        let (voltage, current, power) = (spread(0), spread(1), spread(2));
+
+        Some(event::Readout {
+            voltage: voltage.mean,
+            current: current.mean,
+            power: power.mean,
+            voltage_spread: voltage,
+            current_spread: current,
+            power_spread: power,
+            samples: combined.count,
+            raw_voltage: combined.raw_sum[0] / n,
+            raw_current: combined.raw_sum[1] / n,
+            time: Instant::now(),
+        })
     }
 }
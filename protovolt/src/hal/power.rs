@@ -3,7 +3,10 @@ use core::cell::RefCell;
 use embassy_sync::blocking_mutex::{Mutex, raw::RawMutex};
 use embedded_hal::i2c::I2c;
 
-use crate::hal::device::I2cDeviceWithAddr;
+use crate::hal::{
+    device::I2cDeviceWithAddr,
+    event::{Limits, SelfTestError},
+};
 
 mod stusb4500 {
     pub const ADDR: u8 = 0x28;
@@ -29,6 +32,29 @@ mod stusb4500 {
     // pub const PD_COMMAND_CTRL: u8 = 0x1A;
     pub const DPM_PDO_NUMB: u8 = 0x70;
 
+    pub const PORT_STATUS_1: u8 = 0x0E;
+    pub const PORT_STATUS_ATTACH: u8 = 0x01;
+
+    // CC1_STATE [1:0], CC2_STATE [3:2], as seen by a sink
+    pub const CC_STATUS: u8 = 0x11;
+    pub const CC_STATE_DEFAULT: u8 = 0b01;
+    pub const CC_STATE_POWER_1_5: u8 = 0b10;
+    pub const CC_STATE_POWER_3_0: u8 = 0b11;
+
+    pub const DEVICE_ID: u8 = 0x2F;
+    pub const DEVICE_ID_VALUES: [u8; 2] = [0x21, 0x25];
+
+    // Requested Data Object of the current contract, 4 bytes little endian
+    pub const RDO_REG_STATUS: u8 = 0x91;
+
+    // Data objects of the last message received, 4 bytes little endian each.
+    // Control messages leave them alone, so they hold the source capabilities.
+    pub const RX_DATA_OBJ: u8 = 0x33;
+    pub const RX_DATA_OBJ_COUNT: u8 = 7;
+
+    /// Voltage of the first source PDO, which is always the fixed vSafe5V
+    pub const SOURCE_PDO1_VOLTAGE: f32 = 5.0;
+
     pub const READ: u8 = 0x00;
     pub const WRITE_PL: u8 = 0x01;
     pub const WRITE_SER: u8 = 0x02;
@@ -44,6 +70,18 @@ mod stusb4500 {
 }
 
 pub trait PowerDelivery {
+    /// Check the STUSB4500 answers with its device ID
+    fn init(&mut self) -> Result<(), SelfTestError>;
+
+    /// Whether a source is attached on the Type-C port
+    fn get_attached(&mut self) -> Result<bool, ()>;
+
+    /// Negotiated PD contract, `None` while no explicit contract exists
+    fn get_contract(&mut self) -> Result<Option<Limits>, ()>;
+
+    /// Current advertised by the source's Type-C Rp pull-up
+    fn get_typec_current(&mut self) -> Result<f32, ()>;
+
     /// Read the NVM memory from the device
     #[allow(dead_code)]
     fn read(&mut self) -> Result<(), ()>;
@@ -184,6 +222,34 @@ where
         Ok(pdo)
     }
 
+    /// Voltage of the fixed supply object at `position` (1 to 7) in the source
+    /// capabilities, if one of the sink PDOs asks for it
+    fn read_source_voltage(&mut self, position: u8) -> Result<Option<f32>, ()> {
+        if !(1..=RX_DATA_OBJ_COUNT).contains(&position) {
+            return Ok(None);
+        }
+
+        let mut buf = [0u8; 4];
+        self.i2c_read(RX_DATA_OBJ + (position - 1) * 4, &mut buf)?;
+        let pdo = u32::from_le_bytes(buf);
+
+        // Variable, battery and augmented objects give a range, not a voltage
+        if pdo >> 30 != 0 {
+            return Ok(None);
+        }
+        let voltage = ((pdo >> 10) & 0x3FF) as f32 / 20.0;
+
+        // The STUSB4500 only requests what a sink PDO matches, anything else
+        // is left over from another message
+        for pdo_numb in 1..=self.get_pdo_number()?.clamp(1, 3) {
+            if (self.get_voltage(pdo_numb)? - voltage).abs() < 0.025 {
+                return Ok(Some(voltage));
+            }
+        }
+
+        Ok(None)
+    }
+
     // Helper: write PDO (1-3)
     fn write_pdo(&mut self, pdo_numb: u8, pdo_data: u32) -> Result<(), ()> {
         let addr = 0x85 + (pdo_numb - 1) * 4;
@@ -288,6 +354,65 @@ where
     M: RawMutex,
     BUS: I2c + 'a,
 {
+    fn init(&mut self) -> Result<(), SelfTestError> {
+        let id = self.i2c.read_reg_byte(DEVICE_ID).map_err(|_| SelfTestError::Bus)?;
+
+        match DEVICE_ID_VALUES.contains(&id) {
+            true => Ok(()),
+            false => Err(SelfTestError::Identity),
+        }
+    }
+
+    fn get_attached(&mut self) -> Result<bool, ()> {
+        let status = self.i2c.read_reg_byte(PORT_STATUS_1).map_err(|_| ())?;
+        Ok(status & PORT_STATUS_ATTACH != 0)
+    }
+
+    fn get_contract(&mut self) -> Result<Option<Limits>, ()> {
+        let mut buf = [0u8; 4];
+        self.i2c_read(RDO_REG_STATUS, &mut buf)?;
+        let rdo = u32::from_le_bytes(buf);
+
+        // Object position in the source capabilities, 0 without a contract
+        let position = ((rdo >> 28) & 0x07) as u8;
+        if position == 0 {
+            return Ok(None);
+        }
+
+        let operating_current = ((rdo >> 10) & 0x3FF) as f32 * 0.01;
+
+        // A contract whose voltage can't be read back still holds at least
+        // vSafe5V, so the power budget errs on the low side
+        let voltage = match position {
+            1 => SOURCE_PDO1_VOLTAGE,
+            _ => match self.read_source_voltage(position) {
+                Ok(Some(voltage)) => voltage,
+                _ => {
+                    defmt::warn!("pd: no voltage for source pdo {}", position);
+                    SOURCE_PDO1_VOLTAGE
+                }
+            },
+        };
+
+        Ok(Some(Limits {
+            voltage,
+            current: operating_current,
+        }))
+    }
+
+    fn get_typec_current(&mut self) -> Result<f32, ()> {
+        let status = self.i2c.read_reg_byte(CC_STATUS).map_err(|_| ())?;
+
+        // Only the CC line in use carries the advertisement
+        let cc_state = (status & 0x03).max((status >> 2) & 0x03);
+        Ok(match cc_state {
+            CC_STATE_POWER_3_0 => 3.0,
+            CC_STATE_POWER_1_5 => 1.5,
+            CC_STATE_DEFAULT => 0.5,
+            _ => 0.0,
+        })
+    }
+
     fn read(&mut self) -> Result<(), ()> {
         let mut buffer = [0u8; 1];
         self.read_sectors = true;
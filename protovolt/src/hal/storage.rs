@@ -0,0 +1,327 @@
+//! Wear-levelled key/value store in a reserved region of the W25Q128 QSPI flash.
+//!
+//! The region is used as a ring of erase sectors. Records are appended to the
+//! active sector; once it fills up, the latest record of every key is copied
+//! into the next sector. A sector's header is written after its records, so a
+//! power loss during compaction leaves the previous sector active.
+
+use defmt::*;
+use embassy_rp::{
+    flash::{Blocking, Flash},
+    peripherals::FLASH,
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+pub const FLASH_SIZE: usize = 16 * 1024 * 1024; // W25Q128
+
+// Last 64K of the flash, far past the firmware image
+const REGION_SIZE: u32 = 64 * 1024;
+const REGION_OFFSET: u32 = FLASH_SIZE as u32 - REGION_SIZE;
+const SECTOR_SIZE: u32 = 4096;
+const SECTOR_COUNT: u32 = REGION_SIZE / SECTOR_SIZE;
+
+const SECTOR_MAGIC: u32 = 0x5056_4B56; // "PVKV"
+const SECTOR_HEADER_SIZE: u32 = 8; // magic, sequence
+
+// key, version, length, crc
+const RECORD_HEADER_SIZE: usize = 4;
+const RECORD_ALIGN: u32 = 4;
+const ERASED: u8 = 0xFF;
+
+pub const KEY_COUNT: usize = 8;
+pub const MAX_VALUE_SIZE: usize = 64;
+const MAX_RECORD_SIZE: usize = RECORD_HEADER_SIZE + MAX_VALUE_SIZE;
+
+pub type FlashStorage<'d> = Storage<Flash<'d, FLASH, Blocking, FLASH_SIZE>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum StorageError {
+    Flash,
+    Corrupt,
+    InvalidKey,
+    TooLarge,
+}
+
+pub struct Storage<F: NorFlash + ReadNorFlash> {
+    flash: F,
+
+    mounted: bool,
+    sector: u32,
+    sequence: u32,
+    write_offset: u32,
+
+    /// Offset of the latest valid record of each key within the active sector
+    index: [Option<u32>; KEY_COUNT],
+}
+
+impl<F> Storage<F>
+where
+    F: NorFlash + ReadNorFlash,
+{
+    pub fn new(flash: F) -> Self {
+        Self {
+            flash,
+
+            mounted: false,
+            sector: 0,
+            sequence: 0,
+            write_offset: SECTOR_SIZE,
+
+            index: [None; KEY_COUNT],
+        }
+    }
+
+    /// Find the newest sector and index its records, formatting a blank region
+    pub fn mount(&mut self) -> Result<(), StorageError> {
+        let mut active: Option<(u32, u32)> = None;
+
+        for sector in 0..SECTOR_COUNT {
+            let mut header = [0u8; SECTOR_HEADER_SIZE as usize];
+            self.read(sector, 0, &mut header)?;
+
+            let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+            if magic != SECTOR_MAGIC {
+                continue;
+            }
+            if active.is_none_or(|(_, newest)| sequence > newest) {
+                active = Some((sector, sequence));
+            }
+        }
+
+        match active {
+            Some((sector, sequence)) => {
+                self.sector = sector;
+                self.sequence = sequence;
+                self.scan()?;
+            }
+            None => {
+                info!("storage: formatting region");
+                self.erase(0)?;
+                self.write_header(0, 0)?;
+
+                self.sector = 0;
+                self.sequence = 0;
+                self.index = [None; KEY_COUNT];
+                self.write_offset = SECTOR_HEADER_SIZE;
+            }
+        }
+
+        self.mounted = true;
+        info!(
+            "storage: sector {} sequence {} offset {}",
+            self.sector, self.sequence, self.write_offset
+        );
+
+        Ok(())
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.mounted
+    }
+
+    /// Copy the latest value of `key` into `buf`, returning its schema version and length
+    pub fn get(&mut self, key: u8, buf: &mut [u8]) -> Result<Option<(u8, usize)>, StorageError> {
+        let Some(offset) = *self.index.get(key as usize).ok_or(StorageError::InvalidKey)? else {
+            return Ok(None);
+        };
+
+        let mut record = [0u8; MAX_RECORD_SIZE];
+        let record = self.read_record(self.sector, offset, &mut record)?;
+
+        let (version, value) = (record[1], &record[RECORD_HEADER_SIZE..]);
+        let len = value.len().min(buf.len());
+        buf[..len].copy_from_slice(&value[..len]);
+
+        Ok(Some((version, len)))
+    }
+
+    pub fn set(&mut self, key: u8, version: u8, value: &[u8]) -> Result<(), StorageError> {
+        if key as usize >= KEY_COUNT {
+            return Err(StorageError::InvalidKey);
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(StorageError::TooLarge);
+        }
+
+        // Rewriting an identical value only costs flash wear
+        if let Some(offset) = self.index[key as usize] {
+            let mut stored = [0u8; MAX_RECORD_SIZE];
+            let stored = self.read_record(self.sector, offset, &mut stored)?;
+            if stored[1] == version && &stored[RECORD_HEADER_SIZE..] == value {
+                return Ok(());
+            }
+        }
+
+        let mut record = [ERASED; MAX_RECORD_SIZE];
+        record[0] = key;
+        record[1] = version;
+        record[2] = value.len() as u8;
+        record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + value.len()].copy_from_slice(value);
+        record[3] = crc8(&record[..3], value);
+
+        let size = record_size(value.len());
+        if self.write_offset + size > SECTOR_SIZE {
+            self.compact()?;
+        }
+
+        self.write(self.sector, self.write_offset, &record[..size as usize])?;
+        self.index[key as usize] = Some(self.write_offset);
+        self.write_offset += size;
+
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<(), StorageError> {
+        self.index = [None; KEY_COUNT];
+
+        let mut offset = SECTOR_HEADER_SIZE;
+        while offset + RECORD_HEADER_SIZE as u32 <= SECTOR_SIZE {
+            let mut header = [0u8; RECORD_HEADER_SIZE];
+            self.read(self.sector, offset, &mut header)?;
+
+            if header[0] == ERASED {
+                break;
+            }
+
+            let size = record_size(header[2] as usize);
+            if offset + size > SECTOR_SIZE {
+                // Torn record at the end: force a compaction on the next write
+                offset = SECTOR_SIZE;
+                break;
+            }
+
+            let mut record = [0u8; MAX_RECORD_SIZE];
+            match self.read_record(self.sector, offset, &mut record) {
+                Ok(_) if (header[0] as usize) < KEY_COUNT => {
+                    self.index[header[0] as usize] = Some(offset)
+                }
+                Ok(_) => {}
+                Err(StorageError::Corrupt) => {
+                    warn!("storage: dropping corrupt record at {}", offset)
+                }
+                Err(e) => return Err(e),
+            }
+
+            offset += size;
+        }
+
+        self.write_offset = offset;
+        Ok(())
+    }
+
+    /// Move the latest record of every key into the next sector of the ring
+    fn compact(&mut self) -> Result<(), StorageError> {
+        let next = (self.sector + 1) % SECTOR_COUNT;
+        self.erase(next)?;
+
+        let mut index = [None; KEY_COUNT];
+        let mut offset = SECTOR_HEADER_SIZE;
+
+        for key in 0..KEY_COUNT {
+            let Some(from) = self.index[key] else {
+                continue;
+            };
+
+            let mut record = [0u8; MAX_RECORD_SIZE];
+            let len = self.read_record(self.sector, from, &mut record)?.len();
+
+            let mut padded = [ERASED; MAX_RECORD_SIZE];
+            padded[..len].copy_from_slice(&record[..len]);
+
+            let size = record_size(len - RECORD_HEADER_SIZE);
+            self.write(next, offset, &padded[..size as usize])?;
+
+            index[key] = Some(offset);
+            offset += size;
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.write_header(next, self.sequence)?;
+
+        self.sector = next;
+        self.index = index;
+        self.write_offset = offset;
+
+        Ok(())
+    }
+
+    /// Read and validate a record, returning its header and value
+    fn read_record<'b>(
+        &mut self,
+        sector: u32,
+        offset: u32,
+        buf: &'b mut [u8; MAX_RECORD_SIZE],
+    ) -> Result<&'b [u8], StorageError> {
+        self.read(sector, offset, &mut buf[..RECORD_HEADER_SIZE])?;
+
+        let len = buf[2] as usize;
+        if len > MAX_VALUE_SIZE {
+            return Err(StorageError::Corrupt);
+        }
+
+        let end = RECORD_HEADER_SIZE + len;
+        self.read(
+            sector,
+            offset + RECORD_HEADER_SIZE as u32,
+            &mut buf[RECORD_HEADER_SIZE..end],
+        )?;
+
+        match crc8(&buf[..3], &buf[RECORD_HEADER_SIZE..end]) == buf[3] {
+            true => Ok(&buf[..end]),
+            false => Err(StorageError::Corrupt),
+        }
+    }
+
+    fn write_header(&mut self, sector: u32, sequence: u32) -> Result<(), StorageError> {
+        let mut header = [0u8; SECTOR_HEADER_SIZE as usize];
+        header[..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+
+        self.write(sector, 0, &header)
+    }
+
+    fn read(&mut self, sector: u32, offset: u32, buf: &mut [u8]) -> Result<(), StorageError> {
+        self.flash
+            .read(address(sector, offset), buf)
+            .map_err(|_| StorageError::Flash)
+    }
+
+    fn write(&mut self, sector: u32, offset: u32, buf: &[u8]) -> Result<(), StorageError> {
+        self.flash
+            .write(address(sector, offset), buf)
+            .map_err(|_| StorageError::Flash)
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), StorageError> {
+        let from = address(sector, 0);
+        self.flash
+            .erase(from, from + SECTOR_SIZE)
+            .map_err(|_| StorageError::Flash)
+    }
+}
+
+fn address(sector: u32, offset: u32) -> u32 {
+    REGION_OFFSET + sector * SECTOR_SIZE + offset
+}
+
+fn record_size(value_len: usize) -> u32 {
+    let size = (RECORD_HEADER_SIZE + value_len) as u32;
+    size.div_ceil(RECORD_ALIGN) * RECORD_ALIGN
+}
+
+/// CRC-8 (poly 0x07) over the record header and value
+fn crc8(header: &[u8], value: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in header.iter().chain(value) {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            };
+        }
+    }
+    crc
+}
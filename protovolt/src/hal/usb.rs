@@ -0,0 +1,167 @@
+use defmt::*;
+use embassy_rp::{peripherals::USB, usb::Driver};
+use embassy_sync::{
+    blocking_mutex::raw::ThreadModeRawMutex,
+    channel::{Channel, Sender},
+};
+use embassy_time::{Duration, with_timeout};
+use embassy_usb::{
+    UsbDevice,
+    class::cdc_acm::CdcAcmClass,
+    driver::EndpointError,
+};
+use heapless::Vec;
+
+use crate::app::scpi::{self, ScpiCommand, ScpiError, ScpiResponse};
+use crate::hal::capture;
+
+pub const VID: u16 = 0x2E8A; // Raspberry Pi
+pub const PID: u16 = 0x000A; // RP2040 CDC
+
+pub const MAX_PACKET_SIZE: u16 = 64;
+const LINE_SIZE: usize = 128;
+
+/// Time the app gets to answer a query before the host is left without a reply
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub type UsbDriver = Driver<'static, USB>;
+
+/// Answers to queries, sent back by the app through `HardwareTask::RemoteResponse`
+pub static RESPONSE_CHANNEL: Channel<ThreadModeRawMutex, Option<ScpiResponse>, 4> =
+    Channel::new();
+
+pub fn config() -> embassy_usb::Config<'static> {
+    let mut config = embassy_usb::Config::new(VID, PID);
+    config.manufacturer = Some("ThatAquarel");
+    config.product = Some("ProtoVolt");
+    config.serial_number = Some(env!("CARGO_PKG_VERSION"));
+    config.max_power = 100;
+    config.max_packet_size_0 = MAX_PACKET_SIZE as u8;
+
+    config
+}
+
+#[embassy_executor::task]
+pub async fn run_usb(mut usb: UsbDevice<'static, UsbDriver>) -> ! {
+    usb.run().await
+}
+
+#[embassy_executor::task]
+pub async fn poll_remote(
+    mut class: CdcAcmClass<'static, UsbDriver>,
+    remote_channel: Sender<'static, ThreadModeRawMutex, Result<ScpiCommand, ScpiError>, 8>,
+) {
+    loop {
+        class.wait_connection().await;
+        info!("usb: connected");
+
+        match handle_connection(&mut class, &remote_channel).await {
+            Ok(()) | Err(EndpointError::Disabled) => info!("usb: disconnected"),
+            Err(EndpointError::BufferOverflow) => warn!("usb: buffer overflow"),
+        }
+    }
+}
+
+async fn handle_connection(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    remote_channel: &Sender<'static, ThreadModeRawMutex, Result<ScpiCommand, ScpiError>, 8>,
+) -> Result<(), EndpointError> {
+    let mut packet = [0u8; MAX_PACKET_SIZE as usize];
+    let mut line: Vec<u8, LINE_SIZE> = Vec::new();
+    let mut overflow = false;
+
+    loop {
+        let n = class.read_packet(&mut packet).await?;
+
+        for &byte in &packet[..n] {
+            if byte != b'\n' && byte != b'\r' {
+                overflow |= line.push(byte).is_err();
+                continue;
+            }
+
+            if overflow {
+                remote_channel.send(Err(ScpiError::Syntax)).await;
+            } else if !line.is_empty() {
+                match core::str::from_utf8(&line) {
+                    Ok(text) => handle_line(class, remote_channel, text).await?,
+                    Err(_) => remote_channel.send(Err(ScpiError::Syntax)).await,
+                }
+            }
+
+            line.clear();
+            overflow = false;
+        }
+    }
+}
+
+/// Forward each `;` separated command to the app, writing back query answers
+async fn handle_line(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    remote_channel: &Sender<'static, ThreadModeRawMutex, Result<ScpiCommand, ScpiError>, 8>,
+    line: &str,
+) -> Result<(), EndpointError> {
+    for command in line.split(';').filter(|c| !c.trim().is_empty()) {
+        let command = scpi::parse(command);
+        let query = command.is_ok_and(|c| c.is_query());
+
+        // Drop answers to queries that already timed out
+        while RESPONSE_CHANNEL.try_receive().is_ok() {}
+
+        remote_channel.send(command).await;
+        if !query {
+            continue;
+        }
+
+        match with_timeout(RESPONSE_TIMEOUT, RESPONSE_CHANNEL.receive()).await {
+            Ok(Some(response)) => {
+                write_line(class, response.format().as_bytes()).await?;
+
+                // Far too large for the response channel, read straight from the buffer
+                if let ScpiResponse::Capture(len) = response {
+                    write_capture(class, len).await?;
+                }
+            }
+            Ok(None) => {}
+            Err(_) => warn!("usb: no response to query"),
+        }
+    }
+
+    Ok(())
+}
+
+/// One line per capture sample, after the count in the query answer
+async fn write_capture(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    len: usize,
+) -> Result<(), EndpointError> {
+    for index in 0..len {
+        let Some(sample) = capture::with(|capture| capture.get(index)) else {
+            break;
+        };
+
+        let line = scpi::format_capture_sample(sample.time, sample.voltage, sample.current);
+        write_line(class, line.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+async fn write_line(
+    class: &mut CdcAcmClass<'static, UsbDriver>,
+    line: &[u8],
+) -> Result<(), EndpointError> {
+    let mut buf: Vec<u8, { scpi::RESPONSE_SIZE + 1 }> = Vec::new();
+    let _ = buf.extend_from_slice(line);
+    let _ = buf.push(b'\n');
+
+    for chunk in buf.chunks(MAX_PACKET_SIZE as usize) {
+        class.write_packet(chunk).await?;
+    }
+
+    // A full last packet needs a zero-length one to end the transfer
+    if buf.len() % MAX_PACKET_SIZE as usize == 0 {
+        class.write_packet(&[]).await?;
+    }
+
+    Ok(())
+}
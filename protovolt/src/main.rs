@@ -10,14 +10,15 @@ use core::cell::RefCell;
 
 use defmt::*;
 use embassy_executor::{Executor, Spawner};
-use embassy_rp::gpio::{Output, Pin};
+use embassy_rp::flash::Flash;
+use embassy_rp::gpio::Pin;
 use embassy_rp::i2c::I2c;
 use embassy_rp::multicore::{Stack, spawn_core1};
-use embassy_rp::peripherals::{I2C0, I2C1, PIO0};
+use embassy_rp::peripherals::{I2C0, I2C1, PIO0, USB};
 use embassy_rp::pio;
 use embassy_rp::pio::Pio;
 use embassy_rp::spi::{self, Spi};
-use embassy_rp::{bind_interrupts, i2c};
+use embassy_rp::{bind_interrupts, i2c, usb};
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
 
@@ -25,9 +26,10 @@ use embassy_sync::blocking_mutex::Mutex as I2cMutex;
 
 use embassy_sync::channel::{Channel, Sender};
 use embassy_time::{Duration, Ticker};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
 
-use hal::display::DisplayInterface;
-use hal::event::{AppEvent, HardwareEvent, InterfaceEvent, Task};
+use hal::display::{Backlight, DisplayInterface};
+use hal::event::{AppEvent, Channel as OutputChannel, HardwareEvent, InterfaceEvent, Task};
 use hal::interface::{ButtonsInterface, matrix};
 
 use app::App;
@@ -35,8 +37,15 @@ use task::{handle_display_task, handle_hardware_task};
 use ui::Ui;
 
 use crate::hal::led::LedsInterface;
-use crate::hal::power::{PowerDelivery, PowerDeliveryDevice};
-use crate::hal::{Hal, HalSense, SENSE_CHANNEL, poll_sense};
+use crate::hal::storage::Storage;
+use crate::hal::usb::{poll_remote, run_usb};
+use crate::app::scpi::{ScpiCommand, ScpiError};
+use crate::hal::converter::{ConverterEnable, ConverterInterrupt, ConverterRamp};
+use crate::hal::measure::SenseAlert;
+use crate::hal::{
+    Hal, HalSense, SENSE_CHANNEL, poll_converter_fault, poll_sense, poll_sense_alert,
+    ramp_converter,
+};
 
 use static_cell::StaticCell;
 
@@ -45,6 +54,8 @@ use {defmt_rtt as _, panic_probe as _};
 // Static channels
 pub static INTERFACE_CHANNEL: Channel<ThreadModeRawMutex, InterfaceEvent, 32> = Channel::new();
 pub static HARDWARE_CHANNEL: Channel<ThreadModeRawMutex, HardwareEvent, 32> = Channel::new();
+pub static REMOTE_CHANNEL: Channel<ThreadModeRawMutex, Result<ScpiCommand, ScpiError>, 8> =
+    Channel::new();
 
 // Multicore setup
 static mut CORE1_STACK: Stack<4096> = Stack::new();
@@ -64,11 +75,20 @@ type StaticI2c1 = I2c<'static, I2C1, i2c::Blocking>;
 type StaticI2c1Bus = Mutex<NoopRawMutex, RefCell<StaticI2c1>>;
 static I2C1_BUS: StaticCell<StaticI2c1Bus> = StaticCell::new();
 
+static CONVERTER_ENABLE: StaticCell<[ConverterEnable<'static>; 2]> = StaticCell::new();
+
 type StaticHalSense = HalSense<'static, NoopRawMutex, StaticI2c1>;
 static HAL_SENSE: StaticCell<StaticHalSense> = StaticCell::new();
 
+// USB descriptors and CDC-ACM state
+static USB_CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static USB_BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+static USB_CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+static USB_CDC_STATE: StaticCell<State> = StaticCell::new();
+
 bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
 });
 
 #[embassy_executor::main]
@@ -78,11 +98,13 @@ async fn main(spawner: Spawner) {
     // Power hardware initialization
     let i2c0 = I2c::new_blocking(p.I2C0, p.PIN_1, p.PIN_0, i2c::Config::default());
     let i2c0_bus: Mutex<NoopRawMutex, _> = I2cMutex::new(RefCell::new(i2c0));
-    let i2c0_bus = I2C0_BUS.init(i2c0_bus);
+    // Shared between the Hal and the converter interrupt tasks
+    let i2c0_bus: &'static StaticI2c0Bus = I2C0_BUS.init(i2c0_bus);
 
     let i2c1 = I2c::new_blocking(p.I2C1, p.PIN_23, p.PIN_22, i2c::Config::default());
     let i2c1_bus: Mutex<NoopRawMutex, _> = I2cMutex::new(RefCell::new(i2c1));
-    let i2c1_bus = I2C1_BUS.init(i2c1_bus);
+    // Shared between the sense task and the sense alert tasks
+    let i2c1_bus: &'static StaticI2c1Bus = I2C1_BUS.init(i2c1_bus);
 
     let sense = HalSense::new(i2c1_bus);
     let sense = HAL_SENSE.init(sense);
@@ -92,28 +114,58 @@ async fn main(spawner: Spawner) {
         HARDWARE_CHANNEL.sender()
     )));
 
-    let mut hal = Hal::new(i2c0_bus, p.PIN_24.degrade(), p.PIN_25.degrade());
+    // Settings store on the external QSPI flash
+    let storage = Storage::new(Flash::new_blocking(p.FLASH));
+
+    // Converter EN lines, also pulled low straight from the sense alerts
+    let enable = CONVERTER_ENABLE.init([
+        ConverterEnable::new(p.PIN_24.degrade()),
+        ConverterEnable::new(p.PIN_25.degrade()),
+    ]);
+    let enable: &'static [ConverterEnable<'static>; 2] = enable;
+
+    let mut hal = Hal::new(i2c0_bus, &enable[0], &enable[1], storage);
+
+    // INA226 ALERT lines, SENS_INT_A/B
+    for (alert_pin, channel) in [
+        (p.PIN_12.degrade(), OutputChannel::A),
+        (p.PIN_13.degrade(), OutputChannel::B),
+    ] {
+        let alert = SenseAlert::new(alert_pin, i2c1_bus, &enable[channel as usize], channel);
+        unwrap!(spawner.spawn(poll_sense_alert(alert, HARDWARE_CHANNEL.sender())));
+    }
 
-    // let pd = I2cDevice::new(&i2c0_bus);
-    let mut pd_dev = PowerDeliveryDevice::new(&i2c0_bus);
+    // Converter ~INT lines, CONVERTER_INT_A/B, and voltage ramps
+    for (int_pin, channel) in [
+        (p.PIN_14.degrade(), OutputChannel::A),
+        (p.PIN_15.degrade(), OutputChannel::B),
+    ] {
+        let interrupt = ConverterInterrupt::new(int_pin, i2c0_bus, channel);
+        unwrap!(spawner.spawn(poll_converter_fault(interrupt, HARDWARE_CHANNEL.sender())));
 
-    for pdo in 1..4u8 {
-        info!("({}) voltage (V) {}", pdo, pd_dev.get_voltage(pdo));
-        info!("({}) current (A) {}", pdo, pd_dev.get_current(pdo, ));
-        info!("({}) lower voltage tolerance (%) {}", pdo, pd_dev.get_lower_voltage_limit(pdo));
-        info!("({}) upper voltage tolerance (%) {}", pdo, pd_dev.get_upper_voltage_limit(pdo));
+        let ramp = ConverterRamp::new(i2c0_bus, channel);
+        unwrap!(spawner.spawn(ramp_converter(ramp, HARDWARE_CHANNEL.sender())));
     }
-    info!("pdo number {}", pd_dev.get_pdo_number());
-    info!("flex current {}", pd_dev.get_flex_current());
-    info!("external power {}", pd_dev.get_external_power());
-    info!("usb comm capable {}", pd_dev.get_usb_comm_capable());
-    info!("configuration ok gpio {}", pd_dev.get_config_ok_gpio());
-    info!("extra gpio pin config {}", pd_dev.get_gpio_ctrl());
-    info!("power out >5V only {}", pd_dev.get_power_above_5v_only());
-    info!("operating current {}", pd_dev.get_req_src_current());
 
     // let hal = Hal::new(i2c0_bus, &i2c1_bus, p.PIN_25.degrade(), p.PIN_24.degrade());
 
+    // USB CDC-ACM remote control
+    let mut usb_builder = embassy_usb::Builder::new(
+        usb::Driver::new(p.USB, Irqs),
+        hal::usb::config(),
+        USB_CONFIG_DESCRIPTOR.init([0; 256]),
+        USB_BOS_DESCRIPTOR.init([0; 256]),
+        &mut [],
+        USB_CONTROL_BUF.init([0; 64]),
+    );
+    let cdc = CdcAcmClass::new(
+        &mut usb_builder,
+        USB_CDC_STATE.init(State::new()),
+        hal::usb::MAX_PACKET_SIZE,
+    );
+    unwrap!(spawner.spawn(run_usb(usb_builder.build())));
+    unwrap!(spawner.spawn(poll_remote(cdc, REMOTE_CHANNEL.sender())));
+
     // Buttons (moved to static so Core 1 owns them)
     let buttons = ButtonsInterface::new(
         [p.PIN_8.degrade(), p.PIN_9.degrade(), p.PIN_10.degrade()],
@@ -130,7 +182,7 @@ async fn main(spawner: Spawner) {
         p.PIN_21.degrade(),
         p.PIN_28.degrade(),
     );
-    let mut backlight = Output::new(p.PIN_16, embassy_rp::gpio::Level::Low);
+    let backlight = Backlight::new(p.PWM_SLICE0, p.PIN_16);
 
     // Interfacing LEDs setup
     let pio = Pio::new(p.PIO0, Irqs);
@@ -138,9 +190,8 @@ async fn main(spawner: Spawner) {
 
     // App logic
     let mut app = App::default();
-    let mut ui = Ui::new(&mut display.target, leds);
+    let mut ui = Ui::new(&mut display.target, leds, backlight);
     ui.clear().unwrap();
-    backlight.set_high();
 
     // Start core 1 and spawn poll_interface there
     spawn_core1(
@@ -166,6 +217,8 @@ async fn main(spawner: Spawner) {
             next_app_task = app.handle_event(AppEvent::Hardware(hw_event));
         } else if let Ok(ui_event) = INTERFACE_CHANNEL.try_receive() {
             next_app_task = app.handle_event(AppEvent::Interface(ui_event));
+        } else if let Ok(remote_event) = REMOTE_CHANNEL.try_receive() {
+            next_app_task = app.handle_event(AppEvent::Remote(remote_event));
         }
 
         if let Some(app_task) = next_app_task {
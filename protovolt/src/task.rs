@@ -9,9 +9,9 @@ use embedded_graphics::prelude::DrawTarget;
 use embedded_hal::i2c::I2c;
 
 use crate::hal::event::{
-    Channel, ChannelFocus, ConfirmState, DisplayTask, HardwareEvent, HardwareTask, InterfaceEvent, Limits, PowerType, SetState
+    Channel, ChannelFocus, ConfirmState, DisplayTask, FaultReason, HardwareEvent, HardwareTask, InterfaceEvent, PowerType, SetState
 };
-use crate::hal::Hal;
+use crate::hal::{Hal, capture, usb};
 use crate::ui::{Ui, labels};
 
 pub async fn handle_hardware_task<M, BUS>(
@@ -25,17 +25,26 @@ where
     BUS: I2c,
 {
     match hardware_task {
+        HardwareTask::LoadConfig => {
+            let config = hal.load_config();
+            hw_sender.send(HardwareEvent::ConfigLoaded(config)).await;
+        }
+        HardwareTask::SaveConfig(config) => {
+            if let Err(e) = hal.save_config(&config) {
+                warn!("failed to save config: {}", e);
+            }
+        }
+        HardwareTask::SaveCalibration(channel, calibration) => {
+            if let Err(e) = hal.save_calibration(channel, calibration) {
+                warn!("failed to save calibration {}: {}", channel, e);
+            }
+        }
+        HardwareTask::ApplyCalibration(channel, calibration) => {
+            hal.apply_calibration(channel, calibration);
+        }
         HardwareTask::EnablePowerDelivery => {
-            Timer::after_millis(10).await;
-
-            hw_sender
-                .send(HardwareEvent::PowerDeliveryReady(PowerType::PowerDelivery(
-                    Limits {
-                        voltage: 12.345,
-                        current: 6.789,
-                    },
-                )))
-                .await;
+            let res = hal.enable_power_delivery().await;
+            hw_sender.send(HardwareEvent::PowerDeliveryReady(res)).await;
         }
         HardwareTask::EnableSense => {
             hal.enable_sense().await;
@@ -47,6 +56,18 @@ where
             Timer::after_millis(10).await;
             hw_sender.send(HardwareEvent::ConverterReady(res)).await;
         }
+        HardwareTask::UpdateSenseConfig(presets) => {
+            hal.update_sense_config(presets).await;
+        }
+        HardwareTask::UpdateSenseAlert(limits) => {
+            hal.update_sense_alert(limits).await;
+        }
+        HardwareTask::StartCapture(channel, trigger) => {
+            hal.start_capture(channel, trigger).await;
+        }
+        HardwareTask::CancelCapture => {
+            hal.cancel_capture().await;
+        }
         HardwareTask::EnableReadoutLoop => {
             hal.enable_readout_loop().await;
             info!("enable readout loop");
@@ -59,15 +80,64 @@ where
             Timer::after(duration).await;
             hw_sender.send(event).await;
         }
+        HardwareTask::RemoteResponse(response) => {
+            // Never stall the main loop on a host that stopped reading
+            if usb::RESPONSE_CHANNEL.try_send(response).is_err() {
+                warn!("dropped remote response");
+            }
+        }
         HardwareTask::UpdateConverterVoltage(channel, value) => {
-            hal.update_converter_voltage(channel, value).await.unwrap();
+            let res = hal.update_converter_voltage(channel, value).await;
+            report_converter_fault(channel, res, hw_sender).await;
+        }
+        HardwareTask::RampConverterVoltage(channel, value, rate) => {
+            hal.ramp_converter_voltage(channel, value, rate);
         }
         HardwareTask::UpdateConverterCurrent(channel, value) => {
-            hal.update_converter_current(channel, value).await.unwrap();
+            let res = hal.update_converter_current(channel, value).await;
+            report_converter_fault(channel, res, hw_sender).await;
+        }
+        HardwareTask::UpdateConverterMode(channel, mode) => {
+            let res = hal.update_converter_mode(channel, mode).await;
+            report_converter_fault(channel, res, hw_sender).await;
+        }
+        HardwareTask::UpdateConverterCompensation(channel, resistance) => {
+            let res = hal.update_converter_compensation(channel, resistance).await;
+            report_converter_fault(channel, res, hw_sender).await;
         }
         HardwareTask::UpdateConverterState(channel, state) => {
-            hal.update_converter_state(channel, state).await.unwrap();
+            let res = hal.update_converter_state(channel, state).await;
+            report_converter_fault(channel, res, hw_sender).await;
         }
+        HardwareTask::RestartConverter(channel) => {
+            match hal.restart_converter(channel).await {
+                Ok(()) => hw_sender.send(HardwareEvent::ConverterRestarted(channel)).await,
+                Err(()) => report_converter_fault(channel, Err(()), hw_sender).await,
+            }
+        }
+        HardwareTask::ReadConverterStatus(channel) => {
+            match hal.read_converter_status(channel).await {
+                Ok(status) => {
+                    hw_sender
+                        .send(HardwareEvent::ConverterStatus(channel, status))
+                        .await
+                }
+                Err(()) => report_converter_fault(channel, Err(()), hw_sender).await,
+            }
+        }
+    }
+}
+
+async fn report_converter_fault(
+    channel: Channel,
+    result: Result<(), ()>,
+    hw_sender: &Sender<'_, ThreadModeRawMutex, HardwareEvent, 32>,
+) {
+    if result.is_err() {
+        warn!("converter {} i2c error", channel);
+        hw_sender
+            .send(HardwareEvent::Fault(FaultReason::ConverterBus(channel)))
+            .await;
     }
 }
 
@@ -85,29 +155,20 @@ pub async fn handle_display_task<D, PIO>(
             ui.clear().unwrap();
             ui.boot_splash_screen().unwrap();
         }
-        DisplayTask::ConfirmPowerDelivery(power_type) => {
-            let (usb_type, valid) = match power_type {
-                PowerType::PowerDelivery(_) => (labels::PD, true),
-                PowerType::Standard(_) => (labels::STD, false),
+        DisplayTask::ConfirmPowerDelivery(result) => {
+            let (usb_type, valid) = match result {
+                Ok(PowerType::PowerDelivery(_)) => (labels::PD, true),
+                Ok(PowerType::Standard(_)) => (labels::STD, false),
+                Err(_) => (labels::FAIL, false),
             };
 
             ui.boot_splash_text(0, labels::INPUT, usb_type, valid).unwrap();
         }
         DisplayTask::ConfirmSense(result) => {
-            let (res, valid) = match result {
-                Ok(()) => (labels::PASS, true),
-                Err(()) => (labels::FAIL, false),
-            };
-
-            ui.boot_splash_text(1, labels::SENSE, res, valid).unwrap();
+            ui.boot_splash_self_test(1, labels::SENSE, result).unwrap();
         }
         DisplayTask::ConfirmConverter(result) => {
-            let (res, valid) = match result {
-                Ok(()) => (labels::PASS, true),
-                Err(()) => (labels::FAIL, false),
-            };
-
-            ui.boot_splash_text(2, labels::CONVERTER, res, valid).unwrap();
+            ui.boot_splash_self_test(2, labels::CONVERTER, result).unwrap();
         }
         DisplayTask::SetupMain(power_type, ch_a_limits, ch_b_limits) => {
             ui.clear().unwrap();
@@ -125,15 +186,22 @@ pub async fn handle_display_task<D, PIO>(
                 ui.controls_channel_box(*channel, ChannelFocus::UnselectedInactive).await.unwrap();
                 ui.controls_channel_units(*channel).unwrap();
 
-                ui.controls_submeasurement(*channel, None, limits, ConfirmState::AwaitModify, None).unwrap();
+                ui.controls_submeasurement(*channel, None, limits, [false; 2], ConfirmState::AwaitModify, None).unwrap();
                 ui.controls_submeasurement_tag(*channel, SetState::Set, None, ConfirmState::AwaitModify).unwrap();
             }
         }
         DisplayTask::UpdateReadout(channel, readout) => {
             ui.controls_measurement(channel, readout).unwrap();
         }
-        DisplayTask::UpdateSetpoint(channel,  limits, set_select, confirm_state, precision) => {
-            ui.controls_submeasurement(channel, set_select, limits, confirm_state, precision).unwrap();
+        DisplayTask::UpdateStatistics(channel, statistics) => {
+            ui.controls_statistics(channel, statistics).unwrap();
+        }
+        DisplayTask::UpdateEnergy(channel, count) => {
+            ui.controls_energy(channel, count).unwrap();
+        }
+        DisplayTask::UpdateSetpoint(channel, limits, pending, set_select, confirm_state, precision) => {
+            ui.controls_submeasurement(channel, set_select, limits, pending, confirm_state, precision)
+                .unwrap();
         }
         DisplayTask::UpdateChannelFocus(focus_a, focus_b) => {
             let focuses = [focus_a, focus_b];
@@ -151,5 +219,59 @@ pub async fn handle_display_task<D, PIO>(
         DisplayTask::UpdateSetState(channel, set_state, set_select, confirm_state) => {
             ui.controls_submeasurement_tag(channel, set_state, set_select, confirm_state).unwrap();
         }
+        DisplayTask::UpdateProtection(channel, trip) => {
+            ui.controls_protection(channel, trip).unwrap();
+        }
+        DisplayTask::UpdateChannelOffline(channel) => {
+            ui.controls_offline(channel).unwrap();
+        }
+        DisplayTask::UpdateRegulation(channel, mode) => {
+            ui.controls_regulation(channel, mode).await.unwrap();
+        }
+        DisplayTask::UpdateRegion(channel, region) => {
+            ui.controls_region(channel, region).unwrap();
+        }
+        DisplayTask::UpdateChannelShed(channel) => {
+            ui.controls_shed(channel).unwrap();
+        }
+        DisplayTask::UpdateBudget(percent, state) => {
+            ui.nav_budget(percent, state).unwrap();
+        }
+        DisplayTask::SetupFault(reason, action) => {
+            ui.clear().unwrap();
+            ui.fault_screen(reason).await.unwrap();
+            ui.fault_actions(action).unwrap();
+        }
+        DisplayTask::UpdateRecoveryAction(action) => {
+            ui.fault_actions(action).unwrap();
+        }
+        DisplayTask::SetupSettings => {
+            ui.clear().unwrap();
+            ui.settings_screen().await.unwrap();
+        }
+        DisplayTask::UpdateSettings(settings, cursor) => {
+            ui.settings_items(&settings, cursor).unwrap();
+        }
+        DisplayTask::SetupDeviceInfo(info) => {
+            ui.clear().unwrap();
+            ui.settings_device_info(&info).unwrap();
+        }
+        DisplayTask::SetupCalibration(channel) => {
+            ui.clear().unwrap();
+            ui.settings_calibration(channel).unwrap();
+        }
+        DisplayTask::UpdateCalibration(step) => {
+            ui.settings_calibration_step(&step).unwrap();
+        }
+        DisplayTask::SetupCapture(channel, trigger) => {
+            ui.clear().unwrap();
+            ui.settings_capture(channel, trigger).unwrap();
+        }
+        DisplayTask::ShowCapture => {
+            capture::with(|capture| ui.settings_capture_plot(capture)).unwrap();
+        }
+        DisplayTask::UpdateBrightness(display, leds) => {
+            ui.set_brightness(display, leds).await;
+        }
     }
 }
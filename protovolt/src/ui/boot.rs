@@ -37,7 +37,7 @@ impl<'b> BootScreen<'b> {
         fonts: &Fonts,
         pos: u8,
         title: &'static str,
-        subtitle: &'static str,
+        subtitle: &str,
         valid: bool,
     ) -> Result<(), ()>
     where
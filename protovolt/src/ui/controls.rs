@@ -11,9 +11,14 @@ use u8g2_fonts::{
     types::{FontColor, HorizontalAlignment, VerticalPosition},
 };
 
+use core::fmt::Write;
+use heapless::String;
+
 use crate::{
     app::{DecimalPrecision, SetSelect},
-    hal::event::{Channel, ConfirmState, Limits, Readout},
+    hal::event::{
+        Channel, ConfirmState, EnergyCount, Limits, Readout, Statistics, StatisticsWindow,
+    },
     ui::{Display, Fonts, color_scheme, fmt::format_f32, icons_1x, labels},
 };
 
@@ -166,6 +171,212 @@ impl ControlsScreen {
         Ok(())
     }
 
+    /// Mean, min and max in place of each readout, with the sample count under them
+    pub fn draw_statistics<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        statistics: Statistics,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let (font, label_font) = (&fonts.readout_small, &fonts.info_small);
+        let spreads = [statistics.voltage, statistics.current, statistics.power];
+
+        for (i, spread) in spreads.iter().enumerate() {
+            let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::MEAS_FB_SIZE];
+            let mut fbuf = FrameBuf::new(
+                &mut fbuf_data,
+                ControlsScreen::MEAS_WIDTH,
+                ControlsScreen::MEAS_HEIGHT,
+            );
+
+            font.render_aligned(
+                format_f32::<6>(spread.mean, 3).as_str(),
+                Point::new(ControlsScreen::MEAS_WIDTH as i32 + 1, -1),
+                VerticalPosition::Top,
+                HorizontalAlignment::Right,
+                FontColor::Transparent(color_scheme::FONT_MAIN),
+                &mut fbuf,
+            )
+            .map_err(|_| ())?;
+
+            let mut min: String<12> = String::new();
+            let mut max: String<12> = String::new();
+            write!(min, "{} {}", labels::MIN, format_f32::<6>(spread.min, 3)).map_err(|_| ())?;
+            write!(max, "{} {}", labels::MAX, format_f32::<6>(spread.max, 3)).map_err(|_| ())?;
+
+            let lines = [
+                (labels::AVG, Point::new(0, 3), HorizontalAlignment::Left),
+                (min.as_str(), Point::new(0, 20), HorizontalAlignment::Left),
+                (
+                    max.as_str(),
+                    Point::new(ControlsScreen::MEAS_WIDTH as i32, 20),
+                    HorizontalAlignment::Right,
+                ),
+            ];
+            for (text, position, alignment) in lines {
+                label_font
+                    .render_aligned(
+                        text,
+                        position,
+                        VerticalPosition::Top,
+                        alignment,
+                        FontColor::Transparent(color_scheme::UNSELECTED),
+                        &mut fbuf,
+                    )
+                    .map_err(|_| ())?;
+            }
+
+            let top_left = Point::new(122 - ControlsScreen::MEAS_WIDTH as i32, 30 + 62 * i as i32);
+            let area = Rectangle::new(top_left, fbuf.size());
+
+            target.fill_contiguous(&area, fbuf_data).map_err(|_| ())?;
+        }
+
+        self.draw_statistics_count(target, fonts, statistics)
+    }
+
+    const COUNT_HEIGHT: usize = 10;
+    const COUNT_FB_SIZE: usize = ControlsScreen::MEAS_WIDTH * ControlsScreen::COUNT_HEIGHT;
+
+    /// Window and sample count, under the power readout
+    fn draw_statistics_count<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        statistics: Statistics,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::COUNT_FB_SIZE];
+        let mut fbuf = FrameBuf::new(
+            &mut fbuf_data,
+            ControlsScreen::MEAS_WIDTH,
+            ControlsScreen::COUNT_HEIGHT,
+        );
+
+        let window = match statistics.window {
+            StatisticsWindow::Rolling => labels::ROLLING,
+            StatisticsWindow::SinceReset => labels::SINCE_RESET,
+        };
+
+        let mut count: String<16> = String::new();
+        write!(count, "{} {}", labels::SAMPLES, statistics.count).map_err(|_| ())?;
+
+        let lines = [
+            (window, Point::new(0, -1), HorizontalAlignment::Left),
+            (
+                count.as_str(),
+                Point::new(ControlsScreen::MEAS_WIDTH as i32, -1),
+                HorizontalAlignment::Right,
+            ),
+        ];
+        for (text, position, alignment) in lines {
+            fonts
+                .info_small
+                .render_aligned(
+                    text,
+                    position,
+                    VerticalPosition::Top,
+                    alignment,
+                    FontColor::Transparent(color_scheme::UNSELECTED),
+                    &mut fbuf,
+                )
+                .map_err(|_| ())?;
+        }
+
+        let top_left = Point::new(122 - ControlsScreen::MEAS_WIDTH as i32, 30 + 62 * 2 + 34);
+        let area = Rectangle::new(top_left, fbuf.size());
+
+        target.fill_contiguous(&area, fbuf_data).map_err(|_| ())
+    }
+
+    const UNIT_WIDTH: usize = 28;
+    const UNIT_HEIGHT: usize = 16;
+    const UNIT_FB_SIZE: usize = ControlsScreen::UNIT_WIDTH * ControlsScreen::UNIT_HEIGHT;
+
+    /// Charge, energy and elapsed time in place of the readouts, over their units
+    pub fn draw_energy<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        count: EnergyCount,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let seconds = count.elapsed.as_secs();
+        let mut elapsed: String<12> = String::new();
+        write!(elapsed, "{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+            .map_err(|_| ())?;
+
+        let charge = format_f32::<8>(count.charge, counter_decimals(count.charge));
+        let energy = format_f32::<8>(count.energy, counter_decimals(count.energy));
+
+        let rows = [
+            (charge.as_str(), &fonts.readout_large, -1, labels::MILLIAMPERE_HOUR),
+            (energy.as_str(), &fonts.readout_large, -1, labels::MILLIWATT_HOUR),
+            (elapsed.as_str(), &fonts.readout_small, 7, labels::ELAPSED),
+        ];
+
+        for (i, (text, font, y, unit)) in rows.iter().enumerate() {
+            let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::MEAS_FB_SIZE];
+            let mut fbuf = FrameBuf::new(
+                &mut fbuf_data,
+                ControlsScreen::MEAS_WIDTH,
+                ControlsScreen::MEAS_HEIGHT,
+            );
+
+            font.render_aligned(
+                *text,
+                Point::new(ControlsScreen::MEAS_WIDTH as i32 + 1, *y),
+                VerticalPosition::Top,
+                HorizontalAlignment::Right,
+                FontColor::Transparent(color_scheme::FONT_MAIN),
+                &mut fbuf,
+            )
+            .map_err(|_| ())?;
+
+            let top_left = Point::new(122 - ControlsScreen::MEAS_WIDTH as i32, 30 + 62 * i as i32);
+            let area = Rectangle::new(top_left, fbuf.size());
+
+            target.fill_contiguous(&area, fbuf_data).map_err(|_| ())?;
+
+            // The V, A and W drawn with the screen do not fit the counters
+            let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::UNIT_FB_SIZE];
+            let mut fbuf = FrameBuf::new(
+                &mut fbuf_data,
+                ControlsScreen::UNIT_WIDTH,
+                ControlsScreen::UNIT_HEIGHT,
+            );
+
+            fonts
+                .info_small
+                .render_aligned(
+                    *unit,
+                    Point::new(ControlsScreen::UNIT_WIDTH as i32 / 2, 3),
+                    VerticalPosition::Top,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(Rgb565::CSS_WHITE),
+                    &mut fbuf,
+                )
+                .map_err(|_| ())?;
+
+            let top_left = Point::new(
+                140 - ControlsScreen::UNIT_WIDTH as i32 / 2,
+                30 + 62 * i as i32,
+            );
+            let area = Rectangle::new(top_left, fbuf.size());
+
+            target.fill_contiguous(&area, fbuf_data).map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+
     const SUBMEAS_WIDTH: usize = ControlsScreen::MEAS_WIDTH / 2;
     const SUBMEAS_HEIGHT: usize = ControlsScreen::MEAS_HEIGHT / 2 + 8;
     const SUBMEAS_FB_SIZE: usize = ControlsScreen::SUBMEAS_WIDTH * ControlsScreen::SUBMEAS_HEIGHT;
@@ -176,6 +387,7 @@ impl ControlsScreen {
         fonts: &Fonts,
         set_select: Option<SetSelect>,
         limits: Limits,
+        pending: [bool; 2],
         channel: Channel,
         confirm_state: ConfirmState,
         select_precision: Option<DecimalPrecision>,
@@ -226,6 +438,21 @@ impl ControlsScreen {
             )
             .map_err(|_| ())?;
 
+            // Staged edit that Enter has not applied yet
+            if pending[i] {
+                fonts
+                    .info_small
+                    .render_aligned(
+                        labels::PENDING,
+                        Point::new(0, -1),
+                        VerticalPosition::Top,
+                        HorizontalAlignment::Left,
+                        FontColor::Transparent(color),
+                        &mut fbuf,
+                    )
+                    .map_err(|_| ())?;
+            }
+
             if selected {
                 if let Some(precision) = &select_precision {
                     let exp = precision.get_exponent();
@@ -328,4 +555,137 @@ impl ControlsScreen {
 
         Ok(())
     }
+
+    const PROT_WIDTH: usize = 46;
+    const PROT_HEIGHT: usize = 10;
+    const PROT_FB_SIZE: usize = ControlsScreen::PROT_WIDTH * ControlsScreen::PROT_HEIGHT;
+
+    pub fn draw_protection_tag<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        text: Option<&'static str>,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::PROT_FB_SIZE];
+        let mut fbuf = FrameBuf::new(
+            &mut fbuf_data,
+            ControlsScreen::PROT_WIDTH,
+            ControlsScreen::PROT_HEIGHT,
+        );
+
+        if let Some(text) = text {
+            fonts
+                .info_small
+                .render_aligned(
+                    text,
+                    Point::new(ControlsScreen::PROT_WIDTH as i32, -1),
+                    VerticalPosition::Top,
+                    HorizontalAlignment::Right,
+                    FontColor::Transparent(color_scheme::FAULT_SELECTED),
+                    &mut fbuf,
+                )
+                .map_err(|_| ())?;
+        }
+
+        let top_left = Point::new(150 - ControlsScreen::PROT_WIDTH as i32, 6);
+        let area = Rectangle::new(top_left, fbuf.size());
+
+        target.fill_contiguous(&area, fbuf_data).map_err(|_| ())
+    }
+
+    const MODE_WIDTH: usize = 22;
+    const MODE_HEIGHT: usize = 10;
+    const MODE_FB_SIZE: usize = ControlsScreen::MODE_WIDTH * ControlsScreen::MODE_HEIGHT;
+
+    pub fn draw_regulation_tag<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        tag: Option<(&'static str, Rgb565)>,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::MODE_FB_SIZE];
+        let mut fbuf = FrameBuf::new(
+            &mut fbuf_data,
+            ControlsScreen::MODE_WIDTH,
+            ControlsScreen::MODE_HEIGHT,
+        );
+
+        if let Some((text, color)) = tag {
+            fonts
+                .info_small
+                .render_aligned(
+                    text,
+                    Point::new(0, -1),
+                    VerticalPosition::Top,
+                    HorizontalAlignment::Left,
+                    FontColor::Transparent(color),
+                    &mut fbuf,
+                )
+                .map_err(|_| ())?;
+        }
+
+        let area = Rectangle::new(Point::new(80, 6), fbuf.size());
+
+        target.fill_contiguous(&area, fbuf_data).map_err(|_| ())
+    }
+
+    const REGION_WIDTH: usize = 30;
+    const REGION_HEIGHT: usize = 8;
+    const REGION_FB_SIZE: usize = ControlsScreen::REGION_WIDTH * ControlsScreen::REGION_HEIGHT;
+
+    /// Converter operating region, under the power unit
+    pub fn draw_region_tag<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        text: Option<&'static str>,
+    ) -> Result<(), ()>
+    where
+        D: Display,
+    {
+        let mut fbuf_data = [color_scheme::BACKGROUND; ControlsScreen::REGION_FB_SIZE];
+        let mut fbuf = FrameBuf::new(
+            &mut fbuf_data,
+            ControlsScreen::REGION_WIDTH,
+            ControlsScreen::REGION_HEIGHT,
+        );
+
+        if let Some(text) = text {
+            fonts
+                .info_small
+                .render_aligned(
+                    text,
+                    Point::new(ControlsScreen::REGION_WIDTH as i32 / 2, -1),
+                    VerticalPosition::Top,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(color_scheme::UNSELECTED),
+                    &mut fbuf,
+                )
+                .map_err(|_| ())?;
+        }
+
+        let top_left = Point::new(
+            140 - ControlsScreen::REGION_WIDTH as i32 / 2,
+            30 + 36 + 62 * 2,
+        );
+        let area = Rectangle::new(top_left, fbuf.size());
+
+        target.fill_contiguous(&area, fbuf_data).map_err(|_| ())
+    }
+}
+
+/// Fewer decimals as a counter grows, to keep it within the readout width
+fn counter_decimals(value: f32) -> u32 {
+    match value {
+        v if v < 10.0 => 3,
+        v if v < 100.0 => 2,
+        v if v < 1000.0 => 1,
+        _ => 0,
+    }
 }
@@ -0,0 +1,124 @@
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{
+        CornerRadii, PrimitiveStyleBuilder, Rectangle, RoundedRectangle, StrokeAlignment,
+    },
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::{
+    hal::event::RecoveryAction,
+    ui::{Fonts, Layout, color_scheme, icons_2x, labels},
+};
+
+pub struct FaultScreen;
+
+impl FaultScreen {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn draw_reason<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        title: &str,
+        subtitle: &str,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let center = layout.center_x();
+
+        fonts
+            .icons_2x
+            .render_aligned(
+                icons_2x::CROSS,
+                Point::new(center, 30),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(color_scheme::FAULT_SELECTED),
+                target,
+            )
+            .map_err(|_| ())?;
+
+        let lines = [
+            (labels::FAULT, &fonts.info_large, color_scheme::FONT_MAIN),
+            (title, &fonts.info_small, color_scheme::FONT_MAIN),
+            (subtitle, &fonts.info_small, color_scheme::FONT_SMALL),
+        ];
+
+        for (i, (text, font, color)) in lines.iter().enumerate() {
+            font.render_aligned(
+                *text,
+                Point::new(center, 62 + 18 * i as i32),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(*color),
+                target,
+            )
+            .map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_actions<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        selected: RecoveryAction,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let font = &fonts.info_small;
+
+        let box_style = PrimitiveStyleBuilder::new()
+            .stroke_width(2)
+            .stroke_alignment(StrokeAlignment::Inside)
+            .fill_color(color_scheme::BACKGROUND);
+
+        let actions = [
+            (RecoveryAction::RetryInit, labels::RETRY_INIT),
+            (RecoveryAction::ContinueDegraded, labels::CONTINUE),
+            (RecoveryAction::DisableOutputs, labels::OUTPUTS_OFF),
+        ];
+
+        let (w, h) = (140, 26);
+        let center = layout.center_x();
+
+        for (i, (action, text)) in actions.iter().enumerate() {
+            let top = 130 + 32 * i as i32;
+
+            let color = if *action == selected {
+                color_scheme::SELECTED
+            } else {
+                color_scheme::UNSELECTED
+            };
+
+            RoundedRectangle::new(
+                Rectangle::new(Point::new(center - w / 2, top), Size::new(w as u32, h)),
+                CornerRadii::new(Size::new(10, 10)),
+            )
+            .into_styled(box_style.stroke_color(color).build())
+            .draw(target)
+            .map_err(|_| ())?;
+
+            font.render_aligned(
+                *text,
+                Point::new(center, top + h as i32 / 2),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(color),
+                target,
+            )
+            .map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+}
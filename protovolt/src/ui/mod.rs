@@ -9,22 +9,32 @@ pub mod fmt;
 
 pub mod boot;
 pub mod controls;
+pub mod fault;
 pub mod navbar;
+pub mod settings;
 
 use boot::BootScreen;
 use controls::ControlsScreen;
+use fault::FaultScreen;
 use navbar::Navbar;
+use settings::SettingsScreen;
+
+use core::fmt::Write;
 
 use embedded_graphics::draw_target::DrawTargetExt;
+use heapless::String;
 use u8g2_fonts::{FontRenderer, fonts};
 
 use crate::{
-    app::{DecimalPrecision, SetSelect},
+    app::{DecimalPrecision, SetSelect, settings::Settings},
     hal::{
-        display::st7789,
+        capture::Capture,
+        display::{Backlight, st7789},
         event::{
-            Channel, ChannelFocus, ConfirmState, FunctionButton, Limits, PowerType, Readout,
-            SetState,
+            BudgetState, CalibrationStep, CaptureTrigger, Channel, ChannelFocus, ConfirmState,
+            ConverterRegion, DeviceInfo, EnergyCount, FaultReason, FunctionButton, Limits,
+            PowerType, ProtectionTrip, Readout, RecoveryAction, RegulationMode, SelfTest,
+            SetState, Statistics,
         },
         led::{LedsColor, LedsInterface},
     },
@@ -40,14 +50,20 @@ where
 {
     pub target: &'a mut D,
     pub led_interface: LedsInterface<'a, PIO>,
+    pub backlight: Backlight<'a>,
 
     pub fonts: Fonts,
     pub layout: Layout,
 
     boot: BootScreen<'a>,
     controls: ControlsScreen,
+    fault: FaultScreen,
+    settings: SettingsScreen,
 
     navbar: Navbar,
+
+    /// Last regulation mode per channel, shown on the channel's second LED
+    regulation: [Option<RegulationMode>; 2],
 }
 
 impl<'a, D, PIO> Ui<'a, D, PIO>
@@ -55,18 +71,27 @@ where
     D: DrawTarget<Color = Rgb565>,
     PIO: Instance,
 {
-    pub fn new(target: &'a mut D, led_interface: LedsInterface<'a, PIO>) -> Self {
+    pub fn new(
+        target: &'a mut D,
+        led_interface: LedsInterface<'a, PIO>,
+        backlight: Backlight<'a>,
+    ) -> Self {
         Self {
             target: target,
             led_interface: led_interface,
+            backlight: backlight,
 
             fonts: Fonts::default(),
             layout: Layout {},
 
             boot: BootScreen::new(),
             controls: ControlsScreen::new(),
+            fault: FaultScreen::new(),
+            settings: SettingsScreen::new(),
 
             navbar: Navbar::new(),
+
+            regulation: [None; 2],
         }
     }
 
@@ -74,6 +99,12 @@ where
         self.target.clear(color_scheme::BACKGROUND).map_err(|_| ())
     }
 
+    pub async fn set_brightness(&mut self, display: u8, leds: u8) {
+        self.backlight.set_brightness(display);
+        self.led_interface.set_brightness(leds);
+        self.led_interface.refresh().await;
+    }
+
     pub fn boot_splash_screen(&mut self) -> Result<(), ()> {
         self.boot
             .draw_splash_screen(&mut *self.target, &mut self.layout)
@@ -97,6 +128,39 @@ where
         )
     }
 
+    /// Self-test line of the boot splash, naming every chip that failed
+    /// along with its error code
+    pub fn boot_splash_self_test(
+        &mut self,
+        index: u8,
+        title: &'static str,
+        result: SelfTest,
+    ) -> Result<(), ()> {
+        let mut subtitle = String::<32>::new();
+        for fault in result.faults() {
+            if !subtitle.is_empty() {
+                subtitle.push_str(", ")?;
+            }
+            write!(subtitle, "{} E{:02X}", labels::chip(fault.chip), fault.error.code())
+                .map_err(|_| ())?;
+        }
+
+        let valid = subtitle.is_empty();
+        if valid {
+            subtitle.push_str(labels::PASS)?;
+        }
+
+        self.boot.draw_splash_text(
+            &mut *self.target,
+            &mut self.layout,
+            &self.fonts,
+            index,
+            title,
+            &subtitle,
+            valid,
+        )
+    }
+
     pub async fn controls_channel_box(
         &mut self,
         channel: Channel,
@@ -113,6 +177,8 @@ where
                 Channel::A => color_scheme::CH_A_UNSELECTED,
                 Channel::B => color_scheme::CH_B_UNSELECTED,
             },
+            ChannelFocus::SelectedFault => color_scheme::FAULT_SELECTED,
+            ChannelFocus::UnselectedFault => color_scheme::FAULT_UNSELECTED,
         };
 
         let text = match channel {
@@ -131,9 +197,12 @@ where
                 Channel::A => LedsColor::ChannelA(color_scheme::LED_OFF, color_scheme::LED_OFF),
                 Channel::B => LedsColor::ChannelB(color_scheme::LED_OFF, color_scheme::LED_OFF),
             },
-            ChannelFocus::SelectedActive | ChannelFocus::UnselectedActive => match channel {
-                Channel::A => LedsColor::ChannelA(color_scheme::LED_CH_A, color_scheme::LED_CH_A),
-                Channel::B => LedsColor::ChannelB(color_scheme::LED_CH_B, color_scheme::LED_CH_B),
+            ChannelFocus::SelectedActive | ChannelFocus::UnselectedActive => {
+                self.active_led_color(channel)
+            }
+            ChannelFocus::SelectedFault | ChannelFocus::UnselectedFault => match channel {
+                Channel::A => LedsColor::ChannelA(color_scheme::LED_FAULT, color_scheme::LED_FAULT),
+                Channel::B => LedsColor::ChannelB(color_scheme::LED_FAULT, color_scheme::LED_FAULT),
             },
         };
 
@@ -153,11 +222,27 @@ where
             .draw_measurements(&mut target, &self.fonts, readout)
     }
 
+    pub fn controls_statistics(
+        &mut self,
+        channel: Channel,
+        statistics: Statistics,
+    ) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+        self.controls
+            .draw_statistics(&mut target, &self.fonts, statistics)
+    }
+
+    pub fn controls_energy(&mut self, channel: Channel, count: EnergyCount) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+        self.controls.draw_energy(&mut target, &self.fonts, count)
+    }
+
     pub fn controls_submeasurement(
         &mut self,
         channel: Channel,
         set_select: Option<SetSelect>,
         limits: Limits,
+        pending: [bool; 2],
         confirm_state: ConfirmState,
         select_precision: Option<DecimalPrecision>,
     ) -> Result<(), ()> {
@@ -167,6 +252,7 @@ where
             &self.fonts,
             set_select,
             limits,
+            pending,
             channel,
             confirm_state,
             select_precision,
@@ -198,11 +284,232 @@ where
         )
     }
 
+    pub fn controls_protection(
+        &mut self,
+        channel: Channel,
+        trip: Option<ProtectionTrip>,
+    ) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+
+        let text = match trip {
+            Some(ProtectionTrip::OverVoltage) => Some(labels::OVP_TRIP),
+            Some(ProtectionTrip::OverCurrent) => Some(labels::OCP_TRIP),
+            Some(ProtectionTrip::ShortCircuit) => Some(labels::SCP_TRIP),
+            None => None,
+        };
+
+        self.controls.draw_protection_tag(&mut target, &self.fonts, text)
+    }
+
+    pub async fn controls_regulation(
+        &mut self,
+        channel: Channel,
+        mode: Option<RegulationMode>,
+    ) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+
+        let tag = match mode {
+            Some(RegulationMode::ConstantVoltage) => Some((labels::CV, color_scheme::CV)),
+            Some(RegulationMode::ConstantCurrent) => Some((labels::CC, color_scheme::CC)),
+            None => None,
+        };
+        self.controls
+            .draw_regulation_tag(&mut target, &self.fonts, tag)?;
+
+        self.regulation[channel as usize] = mode;
+        if mode.is_some() {
+            let led_color = self.active_led_color(channel);
+            self.led_interface.update_refresh(led_color).await;
+        }
+
+        Ok(())
+    }
+
+    pub fn controls_region(
+        &mut self,
+        channel: Channel,
+        region: Option<ConverterRegion>,
+    ) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+
+        let text = match region {
+            Some(ConverterRegion::Buck) => Some(labels::BUCK),
+            Some(ConverterRegion::Boost) => Some(labels::BOOST),
+            Some(ConverterRegion::BuckBoost) => Some(labels::BUCK_BOOST),
+            None => None,
+        };
+
+        self.controls.draw_region_tag(&mut target, &self.fonts, text)
+    }
+
+    fn active_led_color(&self, channel: Channel) -> LedsColor {
+        let channel_color = match channel {
+            Channel::A => color_scheme::LED_CH_A,
+            Channel::B => color_scheme::LED_CH_B,
+        };
+        let mode_color = match self.regulation[channel as usize] {
+            Some(RegulationMode::ConstantVoltage) => color_scheme::LED_CV,
+            Some(RegulationMode::ConstantCurrent) => color_scheme::LED_CC,
+            None => channel_color,
+        };
+
+        match channel {
+            Channel::A => LedsColor::ChannelA(channel_color, mode_color),
+            Channel::B => LedsColor::ChannelB(channel_color, mode_color),
+        }
+    }
+
+    pub async fn fault_screen(&mut self, reason: FaultReason) -> Result<(), ()> {
+        let channel_label = |channel| match channel {
+            Channel::A => labels::CHANNEL_A,
+            Channel::B => labels::CHANNEL_B,
+        };
+
+        let mut subtitle = String::<24>::new();
+        let title = match reason {
+            FaultReason::SelfTest(fault) => {
+                write!(subtitle, "{} E{:02X}", labels::SELF_TEST, fault.error.code())
+                    .map_err(|_| ())?;
+                labels::chip(fault.chip)
+            }
+            FaultReason::SenseBus(channel) => {
+                subtitle.push_str(channel_label(channel))?;
+                labels::SENSE_BUS
+            }
+            FaultReason::ConverterBus(channel) => {
+                subtitle.push_str(channel_label(channel))?;
+                labels::CONVERTER_BUS
+            }
+            FaultReason::Protection(channel, trip) => {
+                subtitle.push_str(channel_label(channel))?;
+                match trip {
+                    ProtectionTrip::OverVoltage => labels::OVP_TRIP,
+                    ProtectionTrip::OverCurrent => labels::OCP_TRIP,
+                    ProtectionTrip::ShortCircuit => labels::SCP_TRIP,
+                }
+            }
+        };
+
+        self.fault.draw_reason(
+            &mut *self.target,
+            &mut self.layout,
+            &self.fonts,
+            title,
+            subtitle.as_str(),
+        )?;
+
+        self.led_interface
+            .update_color(LedsColor::Switch(color_scheme::LED_OFF));
+        self.led_interface
+            .update_color(LedsColor::Settings(color_scheme::LED_OFF));
+        self.led_interface
+            .update_color(LedsColor::Enter(color_scheme::LED_FAULT));
+        self.led_interface.refresh().await;
+
+        Ok(())
+    }
+
+    pub fn controls_offline(&mut self, channel: Channel) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+        self.controls
+            .draw_protection_tag(&mut target, &self.fonts, Some(labels::OFFLINE))
+    }
+
+    pub fn controls_shed(&mut self, channel: Channel) -> Result<(), ()> {
+        let mut target = self.layout.channel_section(&mut *self.target, channel);
+        self.controls
+            .draw_protection_tag(&mut target, &self.fonts, Some(labels::SHED))
+    }
+
+    pub fn fault_actions(&mut self, selected: RecoveryAction) -> Result<(), ()> {
+        self.fault
+            .draw_actions(&mut *self.target, &mut self.layout, &self.fonts, selected)
+    }
+
+    pub async fn settings_screen(&mut self) -> Result<(), ()> {
+        self.settings.draw_title(
+            &mut *self.target,
+            &mut self.layout,
+            &self.fonts,
+            labels::SETTINGS,
+        )?;
+
+        self.led_interface
+            .update_color(LedsColor::Switch(color_scheme::LED_OFF));
+        self.led_interface
+            .update_color(LedsColor::Enter(color_scheme::LED_OFF));
+        self.led_interface
+            .update_color(LedsColor::Settings(color_scheme::LED_ON));
+        self.led_interface.refresh().await;
+
+        Ok(())
+    }
+
+    pub fn settings_items(&mut self, settings: &Settings, cursor: usize) -> Result<(), ()> {
+        self.settings.draw_items(
+            &mut *self.target,
+            &mut self.layout,
+            &self.fonts,
+            settings,
+            cursor,
+        )
+    }
+
+    pub fn settings_calibration(&mut self, channel: Channel) -> Result<(), ()> {
+        let title = match channel {
+            Channel::A => labels::CALIBRATE_A,
+            Channel::B => labels::CALIBRATE_B,
+        };
+        self.settings
+            .draw_title(&mut *self.target, &mut self.layout, &self.fonts, title)
+    }
+
+    pub fn settings_calibration_step(&mut self, step: &CalibrationStep) -> Result<(), ()> {
+        self.settings
+            .draw_calibration(&mut *self.target, &mut self.layout, &self.fonts, step)
+    }
+
+    pub fn settings_capture(
+        &mut self,
+        channel: Channel,
+        trigger: CaptureTrigger,
+    ) -> Result<(), ()> {
+        let title = match channel {
+            Channel::A => labels::CAPTURE_A,
+            Channel::B => labels::CAPTURE_B,
+        };
+        self.settings
+            .draw_title(&mut *self.target, &mut self.layout, &self.fonts, title)?;
+        self.settings
+            .draw_capture_armed(&mut *self.target, &mut self.layout, &self.fonts, trigger)
+    }
+
+    pub fn settings_capture_plot(&mut self, capture: &Capture) -> Result<(), ()> {
+        self.settings
+            .draw_capture_plot(&mut *self.target, &mut self.layout, &self.fonts, capture)
+    }
+
+    pub fn settings_device_info(&mut self, info: &DeviceInfo) -> Result<(), ()> {
+        self.settings.draw_title(
+            &mut *self.target,
+            &mut self.layout,
+            &self.fonts,
+            labels::DEVICE_INFO,
+        )?;
+        self.settings
+            .draw_device_info(&mut *self.target, &mut self.layout, &self.fonts, info)
+    }
+
     pub fn nav_power_info(&mut self, power_type: PowerType) -> Result<(), ()> {
         self.navbar
             .draw_power_info(&mut *self.target, &self.fonts, power_type)
     }
 
+    pub fn nav_budget(&mut self, percent: u8, state: BudgetState) -> Result<(), ()> {
+        self.navbar
+            .draw_budget(&mut *self.target, &self.fonts, percent, state)
+    }
+
     pub async fn nav_buttons(
         &mut self,
         confirm_state: ConfirmState,
@@ -352,13 +659,26 @@ pub mod color_scheme {
     pub const CH_B_SELECTED: Rgb565 = Rgb565::CSS_BLUE;
     pub const CH_B_UNSELECTED: Rgb565 = Rgb565::CSS_DARK_BLUE;
 
+    pub const FAULT_SELECTED: Rgb565 = Rgb565::CSS_ORANGE;
+    pub const FAULT_UNSELECTED: Rgb565 = Rgb565::CSS_DARK_ORANGE;
+
+    pub const CV: Rgb565 = Rgb565::CSS_LIME;
+    pub const CC: Rgb565 = Rgb565::CSS_YELLOW;
+
+    pub const BUDGET_EXCEEDED: Rgb565 = Rgb565::CSS_RED;
+
     pub const LED_OFF: RGB8 = RGB8::new(0, 0, 0);
     pub const LED_ON: RGB8 = RGB8::new(10, 10, 10);
     pub const LED_CH_A: RGB8 = RGB8::new(10, 0, 0);
     pub const LED_CH_B: RGB8 = RGB8::new(0, 0, 10);
+    pub const LED_FAULT: RGB8 = RGB8::new(10, 4, 0);
+    pub const LED_CV: RGB8 = RGB8::new(0, 10, 0);
+    pub const LED_CC: RGB8 = RGB8::new(10, 10, 0);
 }
 
 pub mod labels {
+    use crate::hal::event::{Channel, Chip};
+
     // Boot
     pub const INPUT: &'static str = "INPUT";
     pub const PD: &'static str = "USB-C PD";
@@ -377,8 +697,132 @@ pub mod labels {
     pub const VOLT: &'static str = "V";
     pub const AMPERE: &'static str = "A";
     pub const WATT: &'static str = "W";
+    pub const VOLT_PER_SECOND: &'static str = "V/s";
+    pub const MILLIOHM: &'static str = "mOhm";
 
     pub const SET: &'static str = "SET";
+    pub const PENDING: &'static str = "*";
     pub const OVP: &'static str = "OVP";
     pub const OCP: &'static str = "OCP";
+
+    pub const AVG: &'static str = "AVG";
+    pub const MIN: &'static str = "MIN";
+    pub const MAX: &'static str = "MAX";
+    pub const ROLLING: &'static str = "ROLLING";
+    pub const SINCE_RESET: &'static str = "SINCE RESET";
+    pub const SAMPLES: &'static str = "N";
+    pub const MILLIAMPERE_HOUR: &'static str = "mAh";
+    pub const MILLIWATT_HOUR: &'static str = "mWh";
+    pub const ELAPSED: &'static str = "TIME";
+
+    pub const OVP_TRIP: &'static str = "OVP TRIP";
+    pub const OCP_TRIP: &'static str = "OCP TRIP";
+    pub const SCP_TRIP: &'static str = "SCP TRIP";
+
+    // Fault
+    pub const FAULT: &'static str = "FAULT";
+    pub const SELF_TEST: &'static str = "SELF-TEST";
+    pub const SENSE_BUS: &'static str = "SENSE I2C";
+    pub const CONVERTER_BUS: &'static str = "CONVERTER I2C";
+
+    pub const OFFLINE: &'static str = "OFFLINE";
+    pub const CV: &'static str = "CV";
+    pub const CC: &'static str = "CC";
+    pub const BUCK: &'static str = "BUCK";
+    pub const BOOST: &'static str = "BOOST";
+    pub const BUCK_BOOST: &'static str = "B-B";
+    pub const SHED: &'static str = "SHED";
+
+    pub const INA226_A: &'static str = "INA226 A";
+    pub const INA226_B: &'static str = "INA226 B";
+    pub const TPS55289_A: &'static str = "TPS55289 A";
+    pub const TPS55289_B: &'static str = "TPS55289 B";
+    pub const STUSB4500: &'static str = "STUSB4500";
+
+    // Settings
+    pub const SETTINGS: &'static str = "SETTINGS";
+    pub const DISPLAY_BRIGHTNESS: &'static str = "DISPLAY BRIGHTNESS";
+    pub const LED_BRIGHTNESS: &'static str = "LED BRIGHTNESS";
+    pub const DEFAULT_VOLTAGE: &'static str = "DEFAULT VOLTAGE";
+    pub const DEFAULT_CURRENT: &'static str = "DEFAULT CURRENT";
+    pub const APPLY_DEFAULTS: &'static str = "APPLY DEFAULTS";
+    pub const PROTECTION: &'static str = "PROTECTION";
+    pub const POWER_BUDGET: &'static str = "POWER BUDGET";
+    pub const RAMP_A: &'static str = "RAMP A";
+    pub const RAMP_B: &'static str = "RAMP B";
+    pub const LIGHT_LOAD_A: &'static str = "LIGHT LOAD A";
+    pub const LIGHT_LOAD_B: &'static str = "LIGHT LOAD B";
+    pub const OVER_CURRENT_A: &'static str = "OVERCURRENT A";
+    pub const OVER_CURRENT_B: &'static str = "OVERCURRENT B";
+    pub const DISCHARGE_A: &'static str = "DISCHARGE A";
+    pub const DISCHARGE_B: &'static str = "DISCHARGE B";
+    pub const DOUBLE_FREQUENCY_A: &'static str = "FSW DOUBLING A";
+    pub const DOUBLE_FREQUENCY_B: &'static str = "FSW DOUBLING B";
+    pub const CABLE_A: &'static str = "CABLE A";
+    pub const CABLE_B: &'static str = "CABLE B";
+    pub const CABLE_TRIM_A: &'static str = "CABLE TRIM A";
+    pub const CABLE_TRIM_B: &'static str = "CABLE TRIM B";
+    pub const VOLTAGE_LOOP: &'static str = "CLOSED LOOP";
+    pub const ENERGY_HOLD: &'static str = "HOLD ENERGY";
+    pub const SENSE_A: &'static str = "SENSE A";
+    pub const SENSE_B: &'static str = "SENSE B";
+    pub const CAPTURE_TRIGGER: &'static str = "CAPTURE TRIGGER";
+    pub const CAPTURE_A: &'static str = "CAPTURE A";
+    pub const CAPTURE_B: &'static str = "CAPTURE B";
+    pub const CALIBRATE_A: &'static str = "CALIBRATE A";
+    pub const CALIBRATE_B: &'static str = "CALIBRATE B";
+    pub const DEVICE_INFO: &'static str = "DEVICE INFO";
+
+    // Calibration
+    pub const STEP: &'static str = "STEP";
+    pub const OUTPUT: &'static str = "OUTPUT";
+    pub const DMM: &'static str = "DMM";
+    pub const DMM_VOLTAGE: &'static str = "VOLTAGE";
+    pub const DMM_CURRENT: &'static str = "CURRENT SHORT";
+    pub const MEASURED: &'static str = "MEASURED";
+    pub const DIGIT: &'static str = "DIGIT";
+    pub const REJECTED: &'static str = "REJECTED";
+    pub const RETRY: &'static str = "RETRY";
+
+    // Capture
+    pub const TRIGGER: &'static str = "TRIGGER";
+    pub const OUTPUT_ON: &'static str = "OUTPUT ON";
+    pub const STATUS: &'static str = "STATUS";
+    pub const ARMED: &'static str = "ARMED";
+    pub const ENTER: &'static str = "ENTER";
+    pub const PEAK_CURRENT: &'static str = "PEAK CURRENT";
+    pub const WINDOW: &'static str = "WINDOW";
+    pub const MILLISECOND: &'static str = "ms";
+
+    pub const CHANNEL: &'static str = "CHANNEL";
+    pub const OFF: &'static str = "OFF";
+    pub const ON: &'static str = "ON";
+    pub const PFM: &'static str = "PFM";
+    pub const FPWM: &'static str = "FPWM";
+    pub const HICCUP: &'static str = "HICCUP";
+    pub const LATCH: &'static str = "LATCH";
+    pub const LIMIT: &'static str = "LIMIT";
+    pub const SHED_A: &'static str = "SHED A";
+    pub const SHED_B: &'static str = "SHED B";
+    pub const FAST: &'static str = "FAST";
+    pub const NORMAL: &'static str = "NORMAL";
+    pub const LOW_NOISE: &'static str = "LOW NOISE";
+
+    pub const FIRMWARE: &'static str = "FIRMWARE";
+    pub const FIRMWARE_VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    pub const UNTESTED: &'static str = "-";
+
+    pub const RETRY_INIT: &'static str = "RETRY INIT";
+    pub const CONTINUE: &'static str = "CONTINUE";
+    pub const OUTPUTS_OFF: &'static str = "OUTPUTS OFF";
+
+    pub fn chip(chip: Chip) -> &'static str {
+        match chip {
+            Chip::Ina226(Channel::A) => INA226_A,
+            Chip::Ina226(Channel::B) => INA226_B,
+            Chip::Tps55289(Channel::A) => TPS55289_A,
+            Chip::Tps55289(Channel::B) => TPS55289_B,
+            Chip::Stusb4500 => STUSB4500,
+        }
+    }
 }
@@ -9,7 +9,7 @@ use embedded_graphics::{
 use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
 
 use crate::{
-    hal::event::{ConfirmState, FunctionButton, PowerType},
+    hal::event::{BudgetState, ConfirmState, FunctionButton, PowerType},
     ui::{
         Fonts,
         color_scheme::{self},
@@ -20,6 +20,9 @@ use crate::{
 
 use core::fmt::Write;
 
+use embedded_graphics_framebuf::FrameBuf;
+use heapless::String;
+
 
 pub struct Navbar;
 
@@ -89,6 +92,52 @@ impl Navbar {
         Ok(())
     }
 
+    const BUDGET_WIDTH: usize = 32;
+    const BUDGET_HEIGHT: usize = 12;
+    const BUDGET_FB_SIZE: usize = Navbar::BUDGET_WIDTH * Navbar::BUDGET_HEIGHT;
+
+    pub fn draw_budget<D>(
+        &mut self,
+        target: &mut D,
+        fonts: &Fonts,
+        percent: u8,
+        state: BudgetState,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let mut fbuf_data = [color_scheme::BACKGROUND; Navbar::BUDGET_FB_SIZE];
+        let mut fbuf = FrameBuf::new(&mut fbuf_data, Navbar::BUDGET_WIDTH, Navbar::BUDGET_HEIGHT);
+
+        let color = match state {
+            BudgetState::Normal => color_scheme::FONT_SMALL,
+            BudgetState::Warning => color_scheme::FAULT_SELECTED,
+            BudgetState::Exceeded => color_scheme::BUDGET_EXCEEDED,
+        };
+
+        let mut text = String::<8>::new();
+        write!(text, "{}%", percent).map_err(|_| ())?;
+
+        fonts
+            .info_small
+            .render_aligned(
+                text.as_str(),
+                Point::new(
+                    Navbar::BUDGET_WIDTH as i32,
+                    Navbar::BUDGET_HEIGHT as i32 / 2,
+                ),
+                VerticalPosition::Center,
+                HorizontalAlignment::Right,
+                FontColor::Transparent(color),
+                &mut fbuf,
+            )
+            .map_err(|_| ())?;
+
+        let area = Rectangle::new(Point::new(96, 10), fbuf.size());
+
+        target.fill_contiguous(&area, fbuf_data).map_err(|_| ())
+    }
+
     pub fn draw_button<D>(
         &mut self,
         target: &mut D,
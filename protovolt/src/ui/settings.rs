@@ -0,0 +1,575 @@
+use core::fmt::Write;
+
+use embedded_graphics::{
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{
+        CornerRadii, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, RoundedRectangle,
+        StrokeAlignment,
+    },
+};
+use heapless::String;
+use micromath::F32Ext;
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::{
+    app::{
+        budget::BudgetPolicy,
+        settings::{ProtectionBehaviour, Settings, SettingsItem},
+    },
+    hal::{
+        capture::Capture,
+        event::{
+            CalibrationQuantity, CalibrationStep, CaptureTrigger, Channel, ChipFault,
+            ConverterMode, DeviceInfo, PowerType, SelfTest, SensePreset,
+        },
+    },
+    ui::{Fonts, Layout, color_scheme, fmt::format_f32, labels},
+};
+
+pub struct SettingsScreen;
+
+type Value = String<24>;
+
+impl SettingsScreen {
+    const ROW_TOP: i32 = 40;
+    const ROW_HEIGHT: i32 = 22;
+    const ROW_COUNT: usize = 8;
+    const ROW_MARGIN: i32 = 12;
+
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn draw_title<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        title: &'static str,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        fonts
+            .info_large
+            .render_aligned(
+                title,
+                Point::new(layout.center_x(), 16),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(color_scheme::FONT_MAIN),
+                target,
+            )
+            .map_err(|_| ())?;
+
+        Ok(())
+    }
+
+    pub fn draw_items<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        settings: &Settings,
+        cursor: usize,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        // Scroll so that the cursor always stays within the visible rows
+        let first = cursor.saturating_sub(SettingsScreen::ROW_COUNT - 1);
+        let items = SettingsItem::ALL.iter().enumerate().skip(first);
+
+        for (row, (i, item)) in items.take(SettingsScreen::ROW_COUNT).enumerate() {
+            let (label, value) = item_text(item, settings);
+            self.draw_row(target, layout, fonts, row, label, value.as_str(), i == cursor)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_device_info<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        info: &DeviceInfo,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let mut input = Value::new();
+        match info.power_type {
+            PowerType::PowerDelivery(limits) | PowerType::Standard(limits) => {
+                let usb_type = match info.power_type {
+                    PowerType::PowerDelivery(_) => labels::PD,
+                    PowerType::Standard(_) => labels::STD,
+                };
+                write!(
+                    input,
+                    "{} {}V {}A",
+                    usb_type,
+                    format_f32::<8>(limits.voltage, 2),
+                    format_f32::<8>(limits.current, 2)
+                )
+                .map_err(|_| ())?;
+            }
+        }
+
+        let mut firmware = Value::new();
+        firmware.push_str(labels::FIRMWARE_VERSION)?;
+
+        let power_delivery = match info.power_delivery {
+            None => self_test_value(false, None)?,
+            Some(result) => self_test_value(true, result.err())?,
+        };
+        let (sense_a, sense_b) = self_test_text(info.sense)?;
+        let (converter_a, converter_b) = self_test_text(info.converter)?;
+
+        let rows = [
+            (labels::FIRMWARE, firmware),
+            (labels::INPUT, input),
+            (labels::STUSB4500, power_delivery),
+            (labels::INA226_A, sense_a),
+            (labels::INA226_B, sense_b),
+            (labels::TPS55289_A, converter_a),
+            (labels::TPS55289_B, converter_b),
+        ];
+
+        for (row, (label, value)) in rows.iter().enumerate() {
+            self.draw_row(target, layout, fonts, row, *label, value.as_str(), false)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_calibration<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        step: &CalibrationStep,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let (output, unit, dmm) = match step.quantity {
+            CalibrationQuantity::Voltage => (labels::OUTPUT, labels::VOLT, labels::DMM_VOLTAGE),
+            CalibrationQuantity::Current => (labels::LIMIT, labels::AMPERE, labels::DMM_CURRENT),
+        };
+
+        let mut index = Value::new();
+        write!(index, "{}/{}", step.index + 1, step.count).map_err(|_| ())?;
+
+        let mut point = Value::new();
+        write!(point, "{} {}", format_f32::<8>(step.point, 2), unit).map_err(|_| ())?;
+
+        let mut measured = Value::new();
+        write!(measured, "{} {}", format_f32::<8>(step.measured, 2), unit).map_err(|_| ())?;
+
+        let mut digit = Value::new();
+        let digit_step = 10f32.powi(step.precision.get_exponent() as i32);
+        write!(digit, "{} {}", format_f32::<8>(digit_step, 2), unit).map_err(|_| ())?;
+
+        let mut dmm_value = Value::new();
+        dmm_value.push_str(dmm)?;
+
+        let rows = [
+            (labels::STEP, index, false),
+            (output, point, false),
+            (labels::DMM, dmm_value, false),
+            (labels::MEASURED, measured, true),
+            (labels::DIGIT, digit, false),
+        ];
+
+        for (row, (label, value, selected)) in rows.iter().enumerate() {
+            self.draw_row(target, layout, fonts, row, *label, value.as_str(), *selected)?;
+        }
+
+        // Blank once the retried run is under way, so it does not linger
+        let (label, value) = match step.rejected && step.index == 0 {
+            true => (labels::REJECTED, labels::RETRY),
+            false => ("", ""),
+        };
+        self.draw_row(target, layout, fonts, rows.len(), label, value, false)
+    }
+
+    pub fn draw_capture_armed<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        trigger: CaptureTrigger,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let trigger = trigger_text(trigger);
+
+        let rows = [
+            (labels::TRIGGER, trigger.as_str()),
+            (labels::STATUS, labels::ARMED),
+            (labels::ENTER, labels::OUTPUT_ON),
+        ];
+
+        for (row, (label, value)) in rows.iter().enumerate() {
+            self.draw_row(target, layout, fonts, row, *label, value, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Current as a min/max band and voltage as a trace, one column per slice
+    /// of samples, with the trigger marked
+    pub fn draw_capture_plot<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        capture: &Capture,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let peak_current = capture.samples().map(|s| s.current).fold(0.0, f32::max);
+        let peak_voltage = capture.samples().map(|s| s.voltage).fold(0.0, f32::max);
+
+        let len = capture.len();
+        let window = match (capture.get(0), capture.get(len.saturating_sub(1))) {
+            (Some(first), Some(last)) => (last.time - first.time) / 1000,
+            _ => 0,
+        };
+
+        let mut peak = Value::new();
+        write!(peak, "{} {}", format_f32::<8>(peak_current, 2), labels::AMPERE).map_err(|_| ())?;
+
+        let mut duration = Value::new();
+        write!(duration, "{} {}", window, labels::MILLISECOND).map_err(|_| ())?;
+
+        self.draw_row(target, layout, fonts, 0, labels::PEAK_CURRENT, peak.as_str(), false)?;
+        self.draw_row(target, layout, fonts, 1, labels::WINDOW, duration.as_str(), false)?;
+
+        // The plot takes the rows below
+        let left = SettingsScreen::ROW_MARGIN;
+        let width = layout.width() as i32 - 2 * SettingsScreen::ROW_MARGIN;
+        let top = SettingsScreen::ROW_TOP + 2 * SettingsScreen::ROW_HEIGHT;
+        let height = SettingsScreen::ROW_HEIGHT * (SettingsScreen::ROW_COUNT as i32 - 2) - 2;
+        let bottom = top + height - 1;
+
+        Rectangle::new(Point::new(left, top), Size::new(width as u32, height as u32))
+            .into_styled(PrimitiveStyle::with_fill(color_scheme::BACKGROUND))
+            .draw(target)
+            .map_err(|_| ())?;
+
+        if capture.is_empty() {
+            return Ok(());
+        }
+
+        let y = |value: f32, full_scale: f32| {
+            bottom - ((value / full_scale).clamp(0.0, 1.0) * (height - 1) as f32) as i32
+        };
+        let current_scale = peak_current.max(0.1);
+        let voltage_scale = peak_voltage.max(1.0);
+
+        let marker = PrimitiveStyle::with_stroke(color_scheme::UNSELECTED, 1);
+        let band = PrimitiveStyle::with_stroke(color_scheme::CC, 1);
+
+        for column in 0..width {
+            let first = column as usize * len / width as usize;
+            let last = ((column + 1) as usize * len / width as usize).clamp(first + 1, len);
+
+            let (mut low, mut high, mut voltage) = (f32::MAX, f32::MIN, 0.0);
+            let mut trigger = false;
+            for sample in (first..last).filter_map(|index| capture.get(index)) {
+                low = low.min(sample.current);
+                high = high.max(sample.current);
+                voltage = sample.voltage;
+                trigger |= sample.time == 0;
+            }
+
+            let x = left + column;
+            if trigger {
+                Line::new(Point::new(x, top), Point::new(x, bottom))
+                    .into_styled(marker)
+                    .draw(target)
+                    .map_err(|_| ())?;
+            }
+
+            Line::new(
+                Point::new(x, y(low, current_scale)),
+                Point::new(x, y(high, current_scale)),
+            )
+            .into_styled(band)
+            .draw(target)
+            .map_err(|_| ())?;
+
+            Pixel(Point::new(x, y(voltage, voltage_scale)), color_scheme::CV)
+                .draw(target)
+                .map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_row<D>(
+        &mut self,
+        target: &mut D,
+        layout: &mut Layout,
+        fonts: &Fonts,
+        row: usize,
+        label: &'static str,
+        value: &str,
+        selected: bool,
+    ) -> Result<(), ()>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let font = &fonts.info_small;
+
+        let top = SettingsScreen::ROW_TOP + SettingsScreen::ROW_HEIGHT * row as i32;
+        let width = layout.width() as i32 - 2 * SettingsScreen::ROW_MARGIN;
+        let area = Rectangle::new(
+            Point::new(SettingsScreen::ROW_MARGIN, top),
+            Size::new(width as u32, SettingsScreen::ROW_HEIGHT as u32 - 2),
+        );
+
+        area.into_styled(PrimitiveStyle::with_fill(color_scheme::BACKGROUND))
+            .draw(target)
+            .map_err(|_| ())?;
+
+        let color = if selected {
+            let outline = PrimitiveStyleBuilder::new()
+                .stroke_color(color_scheme::SELECTED)
+                .stroke_width(2)
+                .stroke_alignment(StrokeAlignment::Inside)
+                .build();
+
+            RoundedRectangle::new(area, CornerRadii::new(Size::new(8, 8)))
+                .into_styled(outline)
+                .draw(target)
+                .map_err(|_| ())?;
+
+            color_scheme::FONT_MAIN
+        } else {
+            color_scheme::FONT_SMALL
+        };
+
+        let center_y = top + (SettingsScreen::ROW_HEIGHT - 2) / 2;
+        let padding = 10;
+
+        font.render_aligned(
+            label,
+            Point::new(SettingsScreen::ROW_MARGIN + padding, center_y),
+            VerticalPosition::Center,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(color),
+            target,
+        )
+        .map_err(|_| ())?;
+
+        font.render_aligned(
+            value,
+            Point::new(SettingsScreen::ROW_MARGIN + width - padding, center_y),
+            VerticalPosition::Center,
+            HorizontalAlignment::Right,
+            FontColor::Transparent(color),
+            target,
+        )
+        .map_err(|_| ())?;
+
+        Ok(())
+    }
+}
+
+fn item_text(item: &SettingsItem, settings: &Settings) -> (&'static str, Value) {
+    let mut value = Value::new();
+
+    let label = match item {
+        SettingsItem::DisplayBrightness => {
+            let _ = write!(value, "{} %", settings.display_brightness);
+            labels::DISPLAY_BRIGHTNESS
+        }
+        SettingsItem::LedBrightness => {
+            let _ = write!(value, "{} %", settings.led_brightness);
+            labels::LED_BRIGHTNESS
+        }
+        SettingsItem::DefaultVoltage => {
+            let voltage = format_f32::<8>(settings.default_setpoint.voltage, 2);
+            let _ = write!(value, "{} {}", voltage, labels::VOLT);
+            labels::DEFAULT_VOLTAGE
+        }
+        SettingsItem::DefaultCurrent => {
+            let current = format_f32::<8>(settings.default_setpoint.current, 2);
+            let _ = write!(value, "{} {}", current, labels::AMPERE);
+            labels::DEFAULT_CURRENT
+        }
+        SettingsItem::ApplyDefaults => labels::APPLY_DEFAULTS,
+        SettingsItem::Protection => {
+            let _ = value.push_str(match settings.protection {
+                ProtectionBehaviour::FaultScreen => labels::FAULT,
+                ProtectionBehaviour::ChannelOnly => labels::CHANNEL,
+                ProtectionBehaviour::Off => labels::OFF,
+            });
+            labels::PROTECTION
+        }
+        SettingsItem::PowerBudget => {
+            let _ = value.push_str(match settings.budget {
+                BudgetPolicy::LimitCurrent => labels::LIMIT,
+                BudgetPolicy::ShedA => labels::SHED_A,
+                BudgetPolicy::ShedB => labels::SHED_B,
+                BudgetPolicy::Off => labels::OFF,
+            });
+            labels::POWER_BUDGET
+        }
+        SettingsItem::RampA | SettingsItem::RampB => {
+            let rate = match item {
+                SettingsItem::RampA => settings.ramp_rate[0],
+                _ => settings.ramp_rate[1],
+            };
+            let _ = match rate {
+                0 => value.push_str(labels::OFF).map_err(|_| core::fmt::Error),
+                rate => write!(value, "{} {}", rate, labels::VOLT_PER_SECOND),
+            };
+
+            match item {
+                SettingsItem::RampA => labels::RAMP_A,
+                _ => labels::RAMP_B,
+            }
+        }
+        SettingsItem::LightLoadA | SettingsItem::LightLoadB => {
+            let _ = value.push_str(match mode(item, settings).forced_pwm {
+                true => labels::FPWM,
+                false => labels::PFM,
+            });
+            match item {
+                SettingsItem::LightLoadA => labels::LIGHT_LOAD_A,
+                _ => labels::LIGHT_LOAD_B,
+            }
+        }
+        SettingsItem::OverCurrentA | SettingsItem::OverCurrentB => {
+            let _ = value.push_str(match mode(item, settings).hiccup {
+                true => labels::HICCUP,
+                false => labels::LATCH,
+            });
+            match item {
+                SettingsItem::OverCurrentA => labels::OVER_CURRENT_A,
+                _ => labels::OVER_CURRENT_B,
+            }
+        }
+        SettingsItem::DischargeA | SettingsItem::DischargeB => {
+            let _ = value.push_str(on_off(mode(item, settings).discharge));
+            match item {
+                SettingsItem::DischargeA => labels::DISCHARGE_A,
+                _ => labels::DISCHARGE_B,
+            }
+        }
+        SettingsItem::DoubleFrequencyA | SettingsItem::DoubleFrequencyB => {
+            let _ = value.push_str(on_off(mode(item, settings).double_frequency));
+            match item {
+                SettingsItem::DoubleFrequencyA => labels::DOUBLE_FREQUENCY_A,
+                _ => labels::DOUBLE_FREQUENCY_B,
+            }
+        }
+        SettingsItem::CableA | SettingsItem::CableB => {
+            let channel = item.cable_channel().unwrap_or(Channel::A);
+            let resistance = settings.cable_resistance[channel as usize];
+            let _ = match resistance {
+                0 => value.push_str(labels::OFF).map_err(|_| core::fmt::Error),
+                resistance => write!(value, "{} {}", resistance, labels::MILLIOHM),
+            };
+            match channel {
+                Channel::A => labels::CABLE_A,
+                Channel::B => labels::CABLE_B,
+            }
+        }
+        SettingsItem::CableTrimA | SettingsItem::CableTrimB => {
+            let channel = item.cable_channel().unwrap_or(Channel::A);
+            let _ = value.push_str(on_off(settings.cable_trim[channel as usize]));
+            match channel {
+                Channel::A => labels::CABLE_TRIM_A,
+                Channel::B => labels::CABLE_TRIM_B,
+            }
+        }
+        SettingsItem::VoltageLoop => {
+            let _ = value.push_str(on_off(settings.voltage_loop));
+            labels::VOLTAGE_LOOP
+        }
+        SettingsItem::EnergyHold => {
+            let _ = value.push_str(on_off(settings.energy_hold));
+            labels::ENERGY_HOLD
+        }
+        SettingsItem::SenseA | SettingsItem::SenseB => {
+            let preset = match item {
+                SettingsItem::SenseA => settings.sense_preset[0],
+                _ => settings.sense_preset[1],
+            };
+            let _ = value.push_str(match preset {
+                SensePreset::Fast => labels::FAST,
+                SensePreset::Normal => labels::NORMAL,
+                SensePreset::LowNoise => labels::LOW_NOISE,
+            });
+            match item {
+                SettingsItem::SenseA => labels::SENSE_A,
+                _ => labels::SENSE_B,
+            }
+        }
+        SettingsItem::CaptureTrigger => {
+            value = trigger_text(settings.capture_trigger());
+            labels::CAPTURE_TRIGGER
+        }
+        SettingsItem::CaptureA => labels::CAPTURE_A,
+        SettingsItem::CaptureB => labels::CAPTURE_B,
+        SettingsItem::CalibrateA => labels::CALIBRATE_A,
+        SettingsItem::CalibrateB => labels::CALIBRATE_B,
+        SettingsItem::DeviceInfo => labels::DEVICE_INFO,
+    };
+
+    (label, value)
+}
+
+fn mode(item: &SettingsItem, settings: &Settings) -> ConverterMode {
+    let channel = item.mode_channel().unwrap_or(Channel::A);
+    settings.converter_mode[channel as usize]
+}
+
+fn trigger_text(trigger: CaptureTrigger) -> Value {
+    let mut value = Value::new();
+    let _ = match trigger {
+        CaptureTrigger::OutputEnable => {
+            value.push_str(labels::OUTPUT_ON).map_err(|_| core::fmt::Error)
+        }
+        CaptureTrigger::Current(threshold) => {
+            write!(value, "{} {}", format_f32::<8>(threshold, 2), labels::AMPERE)
+        }
+    };
+
+    value
+}
+
+fn on_off(value: bool) -> &'static str {
+    match value {
+        true => labels::ON,
+        false => labels::OFF,
+    }
+}
+
+/// Outcome of one chip's self-test, once it ran
+fn self_test_value(tested: bool, fault: Option<ChipFault>) -> Result<Value, ()> {
+    let mut value = Value::new();
+    match (tested, fault) {
+        (false, _) => value.push_str(labels::UNTESTED)?,
+        (true, None) => value.push_str(labels::PASS)?,
+        (true, Some(fault)) => {
+            write!(value, "{} E{:02X}", labels::FAIL, fault.error.code()).map_err(|_| ())?
+        }
+    }
+    Ok(value)
+}
+
+fn self_test_text(test: Option<SelfTest>) -> Result<(Value, Value), ()> {
+    let text = |fault| self_test_value(test.is_some(), fault);
+
+    let test_or_default = test.unwrap_or_default();
+    Ok((text(test_or_default.ch_a)?, text(test_or_default.ch_b)?))
+}